@@ -0,0 +1,361 @@
+use std::io::Read;
+use super::error::{Error, ErrorKind};
+
+// Qe_Value, Next_Index_LPS, Next_Index_MPS and Switch_MPS of Table D.2.
+// The last entry is the fixed probability estimate of 0.5 used for sign and refinement bits.
+static QE_TABLE: [(i64, u8, u8, u8); 114] = [
+    (0x5a1d, 1, 1, 1), (0x2586, 14, 2, 0), (0x1114, 16, 3, 0), (0x080b, 18, 4, 0),
+    (0x03d8, 20, 5, 0), (0x01da, 23, 6, 0), (0x00e5, 25, 7, 0), (0x006f, 28, 8, 0),
+    (0x0036, 30, 9, 0), (0x001a, 33, 10, 0), (0x000d, 35, 11, 0), (0x0006, 9, 12, 0),
+    (0x0003, 10, 13, 0), (0x0001, 12, 13, 0), (0x5a7f, 15, 15, 1), (0x3f25, 36, 16, 0),
+    (0x2cf2, 38, 17, 0), (0x207c, 39, 18, 0), (0x17b9, 40, 19, 0), (0x1182, 42, 20, 0),
+    (0x0cef, 43, 21, 0), (0x09a1, 45, 22, 0), (0x072f, 46, 23, 0), (0x055c, 48, 24, 0),
+    (0x0406, 49, 25, 0), (0x0303, 51, 26, 0), (0x0240, 52, 27, 0), (0x01b1, 54, 28, 0),
+    (0x0144, 56, 29, 0), (0x00f5, 57, 30, 0), (0x00b7, 59, 31, 0), (0x008a, 60, 32, 0),
+    (0x0068, 62, 33, 0), (0x004e, 63, 34, 0), (0x003b, 32, 35, 0), (0x002c, 33, 9, 0),
+    (0x5ae1, 37, 37, 1), (0x484c, 64, 38, 0), (0x3a0d, 65, 39, 0), (0x2ef1, 67, 40, 0),
+    (0x261f, 68, 41, 0), (0x1f33, 69, 42, 0), (0x19a8, 70, 43, 0), (0x1518, 72, 44, 0),
+    (0x1177, 73, 45, 0), (0x0e74, 74, 46, 0), (0x0bfb, 75, 47, 0), (0x09f8, 77, 48, 0),
+    (0x0861, 78, 49, 0), (0x0706, 79, 50, 0), (0x05cd, 48, 51, 0), (0x04de, 50, 52, 0),
+    (0x040f, 50, 53, 0), (0x0363, 51, 54, 0), (0x02d4, 52, 55, 0), (0x025c, 53, 56, 0),
+    (0x01f8, 54, 57, 0), (0x01a4, 55, 58, 0), (0x0160, 56, 59, 0), (0x0125, 57, 60, 0),
+    (0x00f6, 58, 61, 0), (0x00cb, 59, 62, 0), (0x00ab, 61, 63, 0), (0x008f, 61, 32, 0),
+    (0x5b12, 65, 65, 1), (0x4d04, 80, 66, 0), (0x412c, 81, 67, 0), (0x37d8, 82, 68, 0),
+    (0x2fe8, 83, 69, 0), (0x293c, 84, 70, 0), (0x2379, 86, 71, 0), (0x1edf, 87, 72, 0),
+    (0x1aa9, 87, 73, 0), (0x174e, 72, 74, 0), (0x1424, 72, 75, 0), (0x119c, 74, 76, 0),
+    (0x0f6b, 74, 77, 0), (0x0d51, 75, 78, 0), (0x0bb6, 77, 79, 0), (0x0a40, 77, 48, 0),
+    (0x5832, 80, 81, 1), (0x4d1c, 88, 82, 0), (0x438e, 89, 83, 0), (0x3bdd, 90, 84, 0),
+    (0x34ee, 91, 85, 0), (0x2eae, 92, 86, 0), (0x299a, 93, 87, 0), (0x2516, 86, 71, 0),
+    (0x5570, 88, 89, 1), (0x4ca9, 95, 90, 0), (0x44d9, 96, 91, 0), (0x3e22, 97, 92, 0),
+    (0x3824, 99, 93, 0), (0x32b4, 99, 94, 0), (0x2e17, 93, 86, 0), (0x56a8, 95, 96, 1),
+    (0x4f46, 101, 97, 0), (0x47e5, 102, 98, 0), (0x41cf, 103, 99, 0), (0x3c3d, 104, 100, 0),
+    (0x375e, 99, 93, 0), (0x5231, 105, 102, 0), (0x4c0f, 106, 103, 0), (0x4639, 107, 104, 0),
+    (0x415e, 103, 99, 0), (0x5627, 105, 106, 1), (0x50e7, 108, 107, 0), (0x4b85, 109, 103, 0),
+    (0x5597, 110, 109, 0), (0x504f, 111, 107, 0), (0x5a10, 110, 111, 1), (0x5522, 112, 109, 0),
+    (0x59eb, 112, 111, 1), (0x5a1d, 113, 113, 0),];
+
+#[derive(Clone, Copy)]
+pub struct ArithConditioning {
+    pub dc_l: [u8;4],
+    pub dc_u: [u8;4],
+    pub ac_k: [u8;4],
+}
+
+impl ArithConditioning {
+    pub fn new() -> ArithConditioning {
+        ArithConditioning {
+            dc_l: [0;4],
+            dc_u: [1;4],
+            ac_k: [5;4],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct ArithRegister {
+    c: i64,
+    a: i64,
+    ct: i32,
+    marker: Option<u8>,
+}
+
+impl ArithRegister {
+    fn reset(&mut self) {
+        self.c = 0;
+        self.a = 0;
+        // force reading 2 initial bytes
+        self.ct = -16;
+        self.marker = None;
+    }
+    fn read_data<T:Read>(&mut self, r:&mut T) -> Result<u8, Error> {
+        if self.marker.is_some() {
+            // zero data is supplied once a marker is hit
+            return Ok(0);
+        }
+        let mut buf = [0];
+        r.read_exact(&mut buf)?;
+        if buf[0] != 0xff {
+            return Ok(buf[0]);
+        }
+        while buf[0] == 0xff {
+            r.read_exact(&mut buf)?;
+        }
+        if buf[0] == 0x00 {
+            return Ok(0xff);
+        }
+        self.marker = Some(buf[0]);
+        Ok(0)
+    }
+    fn decode<T:Read>(&mut self, r:&mut T, st: &mut u8) -> Result<u8, Error> {
+        // renormalization and data input (D.2.6)
+        while self.a < 0x8000 {
+            self.ct -= 1;
+            if self.ct < 0 {
+                let data = self.read_data(r)?;
+                self.c = (self.c << 8) | data as i64;
+                self.ct += 8;
+                if self.ct < 0 {
+                    self.ct += 1;
+                    if self.ct == 0 {
+                        self.a = 0x8000;
+                    }
+                }
+            }
+            self.a <<= 1;
+        }
+        let sv = *st;
+        let (qe, next_lps, next_mps, switch_mps) = QE_TABLE[(sv & 0x7f) as usize];
+        let nl = next_lps | (switch_mps << 7);
+        let nm = next_mps;
+        // decoding and probability estimation (D.2.4, D.2.5)
+        let mut temp = self.a - qe;
+        self.a = temp;
+        temp <<= self.ct;
+        if self.c >= temp {
+            self.c -= temp;
+            // conditional LPS exchange
+            if self.a < qe {
+                self.a = qe;
+                *st = (sv & 0x80) ^ nm;
+            } else {
+                self.a = qe;
+                *st = (sv & 0x80) ^ nl;
+                return Ok((sv ^ 0x80) >> 7);
+            }
+        } else if self.a < 0x8000 {
+            // conditional MPS exchange
+            if self.a < qe {
+                *st = (sv & 0x80) ^ nl;
+                return Ok((sv ^ 0x80) >> 7);
+            }
+            *st = (sv & 0x80) ^ nm;
+        }
+        Ok(sv >> 7)
+    }
+}
+
+#[derive(Clone)]
+pub struct ArithDecoder {
+    register: ArithRegister,
+    conditioning: ArithConditioning,
+    dc_stats: [[u8;64];4],
+    ac_stats: [[u8;256];4],
+    // S0, SS, SP and SN for the 25 pairs of difference classes, then X1 to X15 and M2 to M15 for
+    // small and for large differences above
+    lossless_stats: [[u8;158];4],
+    dc_contexts: [usize;4],
+    fixed_bin: u8,
+}
+
+impl ArithDecoder {
+    pub fn new(conditioning: ArithConditioning) -> ArithDecoder {
+        let mut decoder = ArithDecoder {
+            register: ArithRegister{
+                c: 0,
+                a: 0,
+                ct: 0,
+                marker: None,
+            },
+            conditioning,
+            dc_stats: [[0;64];4],
+            ac_stats: [[0;256];4],
+            lossless_stats: [[0;158];4],
+            dc_contexts: [0;4],
+            fixed_bin: 113,
+        };
+        decoder.reset();
+        decoder
+    }
+    pub fn reset(&mut self) {
+        self.register.reset();
+        self.dc_stats = [[0;64];4];
+        self.ac_stats = [[0;256];4];
+        self.lossless_stats = [[0;158];4];
+        self.dc_contexts = [0;4];
+    }
+    // a marker hit while decoding is consumed from the reader and must be handed back
+    pub fn take_marker(&mut self) -> Option<u8> {
+        self.register.marker.take()
+    }
+    pub fn parse_coeffs<T:Read>(&mut self, rd:&mut T, index: usize, dc_tbl: u8, ac_tbl: u8) -> Result<[i32;64], Error> {
+        let mut buf = [0;64];
+        buf[0] = self.parse_dc_diff(rd, index, dc_tbl)?;
+        self.parse_ac_first(rd, ac_tbl, &mut buf, 1, 63, 0)?;
+        Ok(buf)
+    }
+    pub fn parse_dc_diff<T:Read>(&mut self, rd:&mut T, index: usize, tbl: u8) -> Result<i32, Error> {
+        let tbl = check_table(tbl)?;
+        let reg = &mut self.register;
+        let stats = &mut self.dc_stats[tbl];
+        // F.1.4.4.1, statistics bin S0 depends on the previous difference
+        let mut st = self.dc_contexts[index];
+        if reg.decode(rd, &mut stats[st])? == 0 {
+            self.dc_contexts[index] = 0;
+            return Ok(0);
+        }
+        let sign = reg.decode(rd, &mut stats[st + 1])?;
+        st += 2 + sign as usize;
+        let mut m = reg.decode(rd, &mut stats[st])? as i32;
+        if m != 0 {
+            st = 20;
+            while reg.decode(rd, &mut stats[st])? == 1 {
+                m <<= 1;
+                if m == 0x8000 {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
+                st += 1;
+            }
+        }
+        let l = self.conditioning.dc_l[tbl];
+        let u = self.conditioning.dc_u[tbl];
+        self.dc_contexts[index] = if m < (1 << l) >> 1 {
+            0
+        } else if m > (1 << u) >> 1 {
+            12 + sign as usize * 4
+        } else {
+            4 + sign as usize * 4
+        };
+        decode_magnitude(rd, reg, stats, st + 14, m, sign)
+    }
+    // the difference of a lossless sample, conditioned on the differences da of the sample to its
+    // left and db of the one above (H.1.4.3.1), which are 0 for samples at the edge of the scan
+    pub fn parse_lossless_diff<T:Read>(&mut self, rd:&mut T, tbl: u8, da: i32, db: i32) -> Result<i32, Error> {
+        let tbl = check_table(tbl)?;
+        let reg = &mut self.register;
+        let stats = &mut self.lossless_stats[tbl];
+        let l = self.conditioning.dc_l[tbl];
+        let u = self.conditioning.dc_u[tbl];
+        // zero, small positive, small negative, large positive and large negative
+        let class = |d: i32| {
+            if d.abs() <= (1 << l) >> 1 {
+                0
+            } else if d.abs() <= (1 << u) >> 1 {
+                1 + (d < 0) as usize
+            } else {
+                3 + (d < 0) as usize
+            }
+        };
+        let mut st = 4 * (5 * class(da) + class(db));
+        if reg.decode(rd, &mut stats[st])? == 0 {
+            return Ok(0);
+        }
+        let sign = reg.decode(rd, &mut stats[st + 1])?;
+        st += 2 + sign as usize;
+        let mut m = reg.decode(rd, &mut stats[st])? as i32;
+        if m != 0 {
+            st = if class(db) >= 3 { 129 } else { 100 };
+            while reg.decode(rd, &mut stats[st])? == 1 {
+                m <<= 1;
+                if m == 0x8000 {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
+                st += 1;
+            }
+        }
+        decode_magnitude(rd, reg, stats, st + 14, m, sign)
+    }
+    pub fn refine_dc<T:Read>(&mut self, rd:&mut T, coeffs: &mut [i32;64], al: u8) -> Result<(), Error> {
+        if self.register.decode(rd, &mut self.fixed_bin)? == 1 {
+            coeffs[0] |= 1 << al;
+        }
+        Ok(())
+    }
+    pub fn parse_ac_first<T:Read>(&mut self, rd:&mut T, tbl: u8, coeffs: &mut [i32;64], ss: u8, se: u8, al: u8) -> Result<(), Error> {
+        let tbl = check_table(tbl)?;
+        let reg = &mut self.register;
+        let stats = &mut self.ac_stats[tbl];
+        let kx = self.conditioning.ac_k[tbl] as usize;
+        let mut k = ss as usize;
+        while k <= se as usize {
+            let mut st = 3 * (k - 1);
+            if reg.decode(rd, &mut stats[st])? == 1 {
+                // EOB
+                break;
+            }
+            while reg.decode(rd, &mut stats[st + 1])? == 0 {
+                st += 3;
+                k += 1;
+                if k > se as usize {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
+            }
+            let sign = reg.decode(rd, &mut self.fixed_bin)?;
+            st += 2;
+            let mut m = reg.decode(rd, &mut stats[st])? as i32;
+            if m != 0 && reg.decode(rd, &mut stats[st])? == 1 {
+                m <<= 1;
+                st = if k <= kx { 189 } else { 217 };
+                while reg.decode(rd, &mut stats[st])? == 1 {
+                    m <<= 1;
+                    if m == 0x8000 {
+                        return Err(ErrorKind::InvalidCoefficient.into());
+                    }
+                    st += 1;
+                }
+            }
+            coeffs[k] = decode_magnitude(rd, reg, stats, st + 14, m, sign)? * (1 << al);
+            k += 1;
+        }
+        Ok(())
+    }
+    pub fn parse_ac_refine<T:Read>(&mut self, rd:&mut T, tbl: u8, coeffs: &mut [i32;64], ss: u8, se: u8, al: u8) -> Result<(), Error> {
+        let tbl = check_table(tbl)?;
+        let reg = &mut self.register;
+        let stats = &mut self.ac_stats[tbl];
+        let p1 = 1 << al;
+        let m1 = -1 << al;
+        // EOB of the previous stage
+        let mut kex = se as usize;
+        while kex > 0 && coeffs[kex] == 0 {
+            kex -= 1;
+        }
+        let mut k = ss as usize;
+        while k <= se as usize {
+            let mut st = 3 * (k - 1);
+            if k > kex && reg.decode(rd, &mut stats[st])? == 1 {
+                // EOB
+                break;
+            }
+            loop {
+                if coeffs[k] != 0 {
+                    if reg.decode(rd, &mut stats[st + 2])? == 1 {
+                        coeffs[k] += if coeffs[k] < 0 { m1 } else { p1 };
+                    }
+                    break;
+                }
+                if reg.decode(rd, &mut stats[st + 1])? == 1 {
+                    coeffs[k] = if reg.decode(rd, &mut self.fixed_bin)? == 1 { m1 } else { p1 };
+                    break;
+                }
+                st += 3;
+                k += 1;
+                if k > se as usize {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
+            }
+            k += 1;
+        }
+        Ok(())
+    }
+}
+
+fn check_table(tbl: u8) -> Result<usize, Error> {
+    if tbl >= 4 {
+        return Err(ErrorKind::InvalidSegment(format!("invalid arithmetic table {}", tbl)).into());
+    }
+    Ok(tbl as usize)
+}
+
+// decodes the magnitude bit pattern below the leading bit m (F.24) and applies the sign
+fn decode_magnitude<T:Read>(rd:&mut T, reg: &mut ArithRegister, stats: &mut [u8], st: usize, mut m: i32, sign: u8) -> Result<i32, Error> {
+    let mut v = m;
+    m >>= 1;
+    while m != 0 {
+        if reg.decode(rd, &mut stats[st])? == 1 {
+            v |= m;
+        }
+        m >>= 1;
+    }
+    v += 1;
+    Ok(if sign == 1 { -v } else { v })
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::io;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TableClass {
+    Quantization,
+    HuffmanDc,
+    HuffmanAc,
+}
+
+#[derive(Debug)]
+pub enum ErrorKind {
+    Io(io::Error),
+    // the input ended before the image was complete
+    Truncated,
+    NotJpeg,
+    UnknownMarker(u8),
+    // a marker found where entropy coded data was expected
+    UnexpectedMarker(u8),
+    // SOF index of a coding process that is not supported
+    UnsupportedFrame(u8),
+    UnsupportedPrecision { sof: u8, precision: u8 },
+    UnsupportedComponentCount(u8),
+    MissingTable { class: TableClass, id: u8 },
+    // a scan selects a component id that is not in the frame
+    UnknownComponent(u8),
+    InvalidHuffmanCode,
+    // decoded coefficients that do not fit the block or the scan
+    InvalidCoefficient,
+    BadRestartMarker { expected: u8, found: u8 },
+    // a header segment with values out of range
+    InvalidSegment(String),
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Io(e) => write!(f, "io error: {}", e),
+            ErrorKind::Truncated => write!(f, "unexpected end of data"),
+            ErrorKind::NotJpeg => write!(f, "no SOI found"),
+            ErrorKind::UnknownMarker(m) => write!(f, "unknown marker {:x}", m),
+            ErrorKind::UnexpectedMarker(m) => write!(f, "found marker {:x} while reading image", m),
+            ErrorKind::UnsupportedFrame(index) => write!(f, "unsupported frame type SOF{}", index),
+            ErrorKind::UnsupportedPrecision { sof, precision } => {
+                write!(f, "unsupported precision {} for SOF{}", precision, sof)
+            }
+            ErrorKind::UnsupportedComponentCount(n) => write!(f, "unsupported number of components {}", n),
+            ErrorKind::MissingTable { class, id } => write!(f, "missing {:?} table {}", class, id),
+            ErrorKind::UnknownComponent(id) => write!(f, "scan selects unknown component {}", id),
+            ErrorKind::InvalidHuffmanCode => write!(f, "invalid huffman code"),
+            ErrorKind::InvalidCoefficient => write!(f, "invalid coefficient data"),
+            ErrorKind::BadRestartMarker { expected, found } => {
+                write!(f, "expected marker {:x} found marker {:x}", expected, found)
+            }
+            ErrorKind::InvalidSegment(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// an error with the input byte offset and MCU position (column, row) of the scan where it was detected
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    offset: Option<u64>,
+    mcu: Option<(u64, u64)>,
+}
+
+impl Error {
+    pub fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+    pub fn mcu_position(&self) -> Option<(u64, u64)> {
+        self.mcu
+    }
+    pub(crate) fn at_offset(mut self, offset: u64) -> Error {
+        self.offset.get_or_insert(offset);
+        self
+    }
+    pub(crate) fn at_mcu(mut self, x: u64, y: u64) -> Error {
+        self.mcu.get_or_insert((x, y));
+        self
+    }
+    // a push decode ran out of data, which is not a problem of the input
+    pub(crate) fn is_would_block(&self) -> bool {
+        matches!(&self.kind, ErrorKind::Io(e) if e.kind() == io::ErrorKind::WouldBlock)
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error {
+            kind,
+            offset: None,
+            mcu: None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            ErrorKind::Truncated.into()
+        } else {
+            ErrorKind::Io(e).into()
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {}", offset)?;
+        }
+        if let Some((x, y)) = self.mcu {
+            write!(f, " in MCU x={} y={}", x, y)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ErrorKind::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
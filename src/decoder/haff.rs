@@ -1,61 +1,97 @@
 
-use std::io::Read;
-use failure::format_err;
-use failure::Error;
+use std::io::{self, Read};
+use super::error::{Error, ErrorKind};
+
+// codes up to this length are decoded with a single table lookup
+const LOOKAHEAD_BITS: usize = 9;
 
 pub struct HaffTable {
     pub tc: u8,
     pub id: u8,
-    bits: [u8;16],
     values: [u8;256],
     mincodes: [i32;16],
     maxcodes: [i32;16],
     indices: [i32;16],
+    // indexed by the next LOOKAHEAD_BITS bits, code length << 8 | value, or 0 for longer codes
+    lookup: [u16;1 << LOOKAHEAD_BITS],
 }
 impl HaffTable {
-    pub fn new(tc:u8, id:u8, bits: [u8;16], values: [u8;256]) -> HaffTable {
+    // fails when there are more codes of a length than it has bit patterns left
+    pub fn new(tc:u8, id:u8, bits: [u8;16], values: [u8;256]) -> Result<HaffTable, Error> {
         let mut mincodes = [-1;16];
         let mut maxcodes = [-1;16];
         let mut indices = [-1;16];
+        let mut lookup = [0;1 << LOOKAHEAD_BITS];
         let mut code = 0;
         let mut cumm = 0;
         for i in 0..16 {
-            code = code << 1;
+            code <<= 1;
+            if code + bits[i] as i32 > 1 << (i + 1) {
+                return Err(ErrorKind::InvalidSegment(format!("invalid DHT with {} codes of length {}", bits[i], i + 1)).into());
+            }
             if bits[i] > 0 {
                 indices[i] = cumm as i32;
+                mincodes[i] = code & ((1 << (i + 1)) - 1);
+                if i < LOOKAHEAD_BITS {
+                    let shift = LOOKAHEAD_BITS - i - 1;
+                    for k in 0..bits[i] as usize {
+                        let c = (code as usize + k) << shift;
+                        let entry = ((i as u16 + 1) << 8) | values[cumm as usize + k] as u16;
+                        for v in lookup[c..c + (1 << shift)].iter_mut() {
+                            *v = entry;
+                        }
+                    }
+                }
                 cumm += bits[i];
-                mincodes[i] = (code & ((1 << (i + 1)) - 1)) as i32;
                 code += bits[i] as i32;
-                maxcodes[i] = ((code - 1) & ((1 << (i + 1)) - 1)) as i32;
+                maxcodes[i] = (code - 1) & ((1 << (i + 1)) - 1);
             }
         }
-        HaffTable {
-            tc:tc,
-            id:id,
-            bits:bits,
-            values:values,
-            mincodes:mincodes,
-            maxcodes:maxcodes,
-            indices:indices,
-        }
+        Ok(HaffTable {
+            tc,
+            id,
+            values,
+            mincodes,
+            maxcodes,
+            indices,
+            lookup,
+        })
     }
 }
 
+#[derive(Clone)]
 pub struct HaffDecoder {
-    ptr: u8,
-    buf: u8,
+    // upcoming bits of the entropy coded segment, left aligned
+    acc: u64,
+    bits: u32,
+    // zero bits appended to acc after a marker or the end of the input, which must not be consumed
+    padding: u32,
+    marker: Option<u8>,
+    eof: bool,
+    eobrun: u32,
 }
 
 impl HaffDecoder {
     pub fn new() -> HaffDecoder {
         HaffDecoder{
-            ptr: 0,
-            buf: 0,
+            acc: 0,
+            bits: 0,
+            padding: 0,
+            marker: None,
+            eof: false,
+            eobrun: 0,
         }
     }
     pub fn reset(&mut self) {
-        self.ptr = 0;
-        self.buf = 0;
+        self.acc = 0;
+        self.bits = 0;
+        self.padding = 0;
+        self.marker = None;
+        self.eobrun = 0;
+    }
+    // a marker hit while filling the accumulator is consumed from the reader and must be handed back
+    pub fn take_marker(&mut self) -> Option<u8> {
+        self.marker.take()
     }
     pub fn parse_coeffs<T:Read>(&mut self, rd:&mut T, dc_haff: &HaffTable, ac_haff: &HaffTable) -> Result<[i32;64], Error> {
         let mut buf = [0;64];
@@ -75,11 +111,17 @@ impl HaffDecoder {
                 break;
             } else if ssss == 0 && rrrr == 0xf {
                 // ZRL
+                if ptr + 16 > 64 {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
                 for _ in 0..16 {
                     buf[ptr] = 0;
                     ptr+=1;
                 }
             } else {
+                if ptr + rrrr as usize >= 64 {
+                    return Err(ErrorKind::InvalidCoefficient.into());
+                }
                 for _ in 0..rrrr {
                     buf[ptr] = 0;
                     ptr+=1;
@@ -90,48 +132,262 @@ impl HaffDecoder {
         }
         Ok(buf)
     }
+    pub fn parse_dc_diff<T:Read>(&mut self, rd:&mut T, dc_haff: &HaffTable) -> Result<i32, Error> {
+        let ssss = self.parse_haff(rd, dc_haff)?;
+        self.read_ssss_bits(ssss, rd)
+    }
+    pub fn parse_lossless_diff<T:Read>(&mut self, rd:&mut T, haff: &HaffTable) -> Result<i32, Error> {
+        let ssss = self.parse_haff(rd, haff)?;
+        if ssss == 16 {
+            // difference 32768 has no additional bits
+            return Ok(32768);
+        }
+        self.read_ssss_bits(ssss, rd)
+    }
+    pub fn refine_dc<T:Read>(&mut self, rd:&mut T, coeffs: &mut [i32;64], al: u8) -> Result<(), Error> {
+        if self.read_bit(rd)? == 1 {
+            coeffs[0] |= 1 << al;
+        }
+        Ok(())
+    }
+    pub fn parse_ac_first<T:Read>(&mut self, rd:&mut T, ac_haff: &HaffTable, coeffs: &mut [i32;64], ss: u8, se: u8, al: u8) -> Result<(), Error> {
+        if self.eobrun > 0 {
+            self.eobrun -= 1;
+            return Ok(());
+        }
+        let mut ptr = ss as usize;
+        while ptr <= se as usize {
+            let r = self.parse_haff(rd, ac_haff)?;
+            let rrrr = r >> 4;
+            let ssss = r & 0xf;
+            if ssss == 0 {
+                if rrrr < 0xf {
+                    // EOBn
+                    self.eobrun = (1 << rrrr) - 1;
+                    if rrrr > 0 {
+                        self.eobrun += self.read_bits(rrrr, rd)?;
+                    }
+                    break;
+                }
+                // ZRL
+                ptr += 16;
+                continue;
+            }
+            ptr += rrrr as usize;
+            if ptr > se as usize {
+                return Err(ErrorKind::InvalidCoefficient.into());
+            }
+            coeffs[ptr] = self.read_ssss_bits(ssss, rd)? * (1 << al);
+            ptr += 1;
+        }
+        Ok(())
+    }
+    pub fn parse_ac_refine<T:Read>(&mut self, rd:&mut T, ac_haff: &HaffTable, coeffs: &mut [i32;64], ss: u8, se: u8, al: u8) -> Result<(), Error> {
+        let p1 = 1 << al;
+        let m1 = -1 << al;
+        let mut ptr = ss as usize;
+        if self.eobrun == 0 {
+            while ptr <= se as usize {
+                let r = self.parse_haff(rd, ac_haff)?;
+                let mut rrrr = r >> 4;
+                let ssss = r & 0xf;
+                let mut value = 0;
+                if ssss != 0 {
+                    value = if self.read_bit(rd)? == 1 { p1 } else { m1 };
+                } else if rrrr != 0xf {
+                    // EOBn
+                    self.eobrun = 1 << rrrr;
+                    if rrrr > 0 {
+                        self.eobrun += self.read_bits(rrrr, rd)?;
+                    }
+                    break;
+                }
+                // skip rrrr zero coefficients while refining the nonzero ones on the way
+                while ptr <= se as usize {
+                    if coeffs[ptr] != 0 {
+                        self.refine_ac(rd, &mut coeffs[ptr], p1, m1)?;
+                    } else {
+                        if rrrr == 0 {
+                            break;
+                        }
+                        rrrr -= 1;
+                    }
+                    ptr += 1;
+                }
+                if value != 0 {
+                    if ptr > se as usize {
+                        return Err(ErrorKind::InvalidCoefficient.into());
+                    }
+                    coeffs[ptr] = value;
+                }
+                ptr += 1;
+            }
+        }
+        if self.eobrun > 0 {
+            while ptr <= se as usize {
+                if coeffs[ptr] != 0 {
+                    self.refine_ac(rd, &mut coeffs[ptr], p1, m1)?;
+                }
+                ptr += 1;
+            }
+            self.eobrun -= 1;
+        }
+        Ok(())
+    }
+    fn refine_ac<T:Read>(&mut self, rd:&mut T, coeff: &mut i32, p1: i32, m1: i32) -> Result<(), Error> {
+        if self.read_bit(rd)? == 1 && (*coeff & p1) == 0 {
+            *coeff += if *coeff >= 0 { p1 } else { m1 };
+        }
+        Ok(())
+    }
+    fn read_bits<T:Read>(&mut self, n: u8, rd:&mut T) -> Result<u32, Error> {
+        if n == 0 {
+            return Ok(0)
+        }
+        self.fill(rd)?;
+        let r = (self.acc >> (64 - n as u32)) as u32;
+        self.consume(n as u32)?;
+        Ok(r)
+    }
     fn read_ssss_bits<T:Read>(&mut self, ssss: u8, rd:&mut T) -> Result<i32, Error> {
         if ssss == 0 {
             return Ok(0)
         }
-        let mut r = 0;
-        for _ in 0..ssss {
-            r = (r << 1) + self.read_bit(rd)? as i32
+        // a DC or lossless size symbol of a corrupt table
+        if ssss > 16 {
+            return Err(ErrorKind::InvalidCoefficient.into());
         }
+        let r = self.read_bits(ssss, rd)? as i32;
         if r < (1 << (ssss - 1)) {
             return Ok(r - (1 << ssss) + 1);
         }
         Ok(r)
     }
     fn parse_haff<T:Read>(&mut self, r:&mut T, haff: &HaffTable) -> Result<u8, Error> {
-        let mut cur_bit = 0 as i32;
-        for i in 0..16 {
-            cur_bit = (cur_bit << 1) + self.read_bit(r)? as i32;
+        self.fill(r)?;
+        let entry = haff.lookup[(self.acc >> (64 - LOOKAHEAD_BITS)) as usize];
+        if entry != 0 {
+            self.consume((entry >> 8) as u32)?;
+            return Ok(entry as u8);
+        }
+        // codes longer than LOOKAHEAD_BITS
+        let code = (self.acc >> 48) as i32;
+        for i in LOOKAHEAD_BITS..16 {
+            let cur_bit = code >> (15 - i);
             if haff.indices[i] == -1 {
                 continue;
             }
-            if haff.mincodes[i] <=cur_bit && cur_bit <= haff.maxcodes[i] {
-                //println!("haff:{}",haff.values[(haff.indices[i] + cur_bit - haff.mincodes[i]) as usize]);
+            if haff.mincodes[i] <= cur_bit && cur_bit <= haff.maxcodes[i] {
+                self.consume(i as u32 + 1)?;
                 return Ok(haff.values[(haff.indices[i] + cur_bit - haff.mincodes[i]) as usize])
             }
         }
-        Err(format_err!("haff parse error"))
+        Err(ErrorKind::InvalidHuffmanCode.into())
     }
     fn read_bit<T:Read>(&mut self, r:&mut T) -> Result<u8, Error> {
-        if self.ptr == 0 {
-            let mut buf = [0];
-            r.read_exact(&mut buf)?;
-            self.buf = buf[0];
-            if buf[0] == 0xff {
-                r.read_exact(&mut buf)?;
-                if buf[0] != 0x00 {
-                    return Err(format_err!("found marker {:x} while reading image", buf[0]));
+        Ok(self.read_bits(1, r)? as u8)
+    }
+    fn consume(&mut self, n: u32) -> Result<(), Error> {
+        if n + self.padding > self.bits {
+            return Err(match self.marker {
+                Some(marker) => ErrorKind::UnexpectedMarker(marker),
+                None => ErrorKind::Truncated,
+            }
+            .into());
+        }
+        self.acc <<= n;
+        self.bits -= n;
+        Ok(())
+    }
+    // tops up acc to at least 57 bits, reading bytes up to the next marker
+    fn fill<T:Read>(&mut self, r:&mut T) -> Result<(), Error> {
+        while self.bits <= 56 {
+            let mut byte = 0;
+            if self.marker.is_some() || self.eof {
+                self.padding += 8;
+            } else {
+                match read_byte(r)? {
+                    None => {
+                        self.eof = true;
+                        self.padding += 8;
+                    }
+                    Some(0xff) => {
+                        // 0xff 0x00 is a stuffed 0xff, anything else but fill bytes is a marker
+                        let mut next = read_byte(r)?;
+                        while next == Some(0xff) {
+                            next = read_byte(r)?;
+                        }
+                        match next {
+                            Some(0x00) => byte = 0xff,
+                            Some(m) => {
+                                self.marker = Some(m);
+                                self.padding += 8;
+                            }
+                            None => {
+                                self.eof = true;
+                                self.padding += 8;
+                            }
+                        }
+                    }
+                    Some(b) => byte = b,
                 }
-                // println!("skpped 0xff 0x00");
             }
-            self.ptr = 8;
+            self.acc |= (byte as u64) << (56 - self.bits);
+            self.bits += 8;
         }
-        self.ptr-=1;
-        Ok((self.buf >> self.ptr) & 0x1)
+        Ok(())
+    }
+}
+
+fn read_byte<T:Read>(r:&mut T) -> Result<Option<u8>, Error> {
+    let mut buf = [0];
+    match r.read_exact(&mut buf) {
+        Ok(()) => Ok(Some(buf[0])),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // a DC table with the single code 0 for size 0, and an AC table with the codes 00 for ZRL, 01 for
+    // 14 zeros and a coefficient of size 1 and 10 for 15 zeros and a coefficient of size 1
+    fn tables() -> (HaffTable, HaffTable) {
+        let mut dc_bits = [0; 16];
+        dc_bits[0] = 1;
+        let mut ac_bits = [0; 16];
+        ac_bits[1] = 3;
+        let mut ac_values = [0; 256];
+        ac_values[..3].copy_from_slice(&[0xf0, 0xe1, 0xf1]);
+        (HaffTable::new(0, 0, dc_bits, [0; 256]).unwrap(), HaffTable::new(1, 0, ac_bits, ac_values).unwrap())
+    }
+
+    fn parse(data: &[u8]) -> Result<[i32; 64], Error> {
+        let (dc, ac) = tables();
+        HaffDecoder::new().parse_coeffs(&mut Cursor::new(data), &dc, &ac)
+    }
+
+    #[test]
+    fn run_up_to_the_last_coefficient() {
+        // DC 0, three ZRL and a run of 14 to coefficient 63 with the value 1
+        let coeffs = parse(&[0b0000_0000, 0b1100_0000]).unwrap();
+        assert_eq!(coeffs[63], 1);
+        assert!(coeffs[..63].iter().all(|&c| c == 0));
+    }
+
+    #[test]
+    fn zrl_past_the_block() {
+        let error = parse(&[0b0000_0000, 0b0000_0000]).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::InvalidCoefficient));
+    }
+
+    #[test]
+    fn run_past_the_block() {
+        // three ZRL and a run of 15 from coefficient 49
+        let error = parse(&[0b0000_0001, 0b0100_0000]).unwrap_err();
+        assert!(matches!(error.kind(), ErrorKind::InvalidCoefficient));
     }
 }
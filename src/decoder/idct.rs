@@ -0,0 +1,247 @@
+// coefficients are given in zigzag order and already dequantized
+
+static ZIGZAGS: [[i32; 8]; 8] = [
+    [0, 1, 5, 6, 14, 15, 27, 28],
+    [2, 4, 7, 13, 16, 26, 29, 42],
+    [3, 8, 12, 17, 25, 30, 41, 43],
+    [9, 11, 18, 24, 31, 40, 44, 53],
+    [10, 19, 23, 32, 39, 45, 52, 54],
+    [20, 22, 33, 38, 46, 51, 55, 60],
+    [21, 34, 37, 47, 50, 56, 59, 61],
+    [35, 36, 48, 49, 57, 58, 62, 63],
+];
+
+const CONST_BITS: u32 = 13;
+const FIX_0_211164243: i64 = 1730;
+const FIX_0_298631336: i64 = 2446;
+const FIX_0_390180644: i64 = 3196;
+const FIX_0_509795579: i64 = 4176;
+const FIX_0_541196100: i64 = 4433;
+const FIX_0_601344887: i64 = 4926;
+const FIX_0_720959822: i64 = 5906;
+const FIX_0_765366865: i64 = 6270;
+const FIX_0_850430095: i64 = 6967;
+const FIX_0_899976223: i64 = 7373;
+const FIX_1_061594337: i64 = 8697;
+const FIX_1_175875602: i64 = 9633;
+const FIX_1_272758580: i64 = 10426;
+const FIX_1_451774981: i64 = 11893;
+const FIX_1_501321110: i64 = 12299;
+const FIX_1_847759065: i64 = 15137;
+const FIX_1_961570560: i64 = 16069;
+const FIX_2_053119869: i64 = 16819;
+const FIX_2_172734803: i64 = 17799;
+const FIX_2_562915447: i64 = 20995;
+const FIX_3_072711026: i64 = 25172;
+const FIX_3_624509785: i64 = 29692;
+
+fn descale(x: i64, n: u32) -> i64 {
+    (x + (1 << (n - 1))) >> n
+}
+
+fn natural_order(coeffs: &[i32; 64]) -> [[i64; 8]; 8] {
+    let mut block = [[0_i64; 8]; 8];
+    for (row, zigzag_row) in block.iter_mut().zip(ZIGZAGS.iter()) {
+        for (v, &z) in row.iter_mut().zip(zigzag_row.iter()) {
+            *v = coeffs[z as usize] as i64;
+        }
+    }
+    block
+}
+
+fn to_sample(v: i64, precision: u8) -> u16 {
+    let max = (1 << precision) - 1;
+    i64::min(i64::max(v + (1 << (precision - 1)), 0), max) as u16
+}
+
+// one dimensional 8 point IDCT of the LLM algorithm as in libjpeg's jidctint.c,
+// the results are scaled up by 2^CONST_BITS and left for the caller to descale
+fn idct_1d(v: &[i64; 8]) -> [i64; 8] {
+    // even part
+    let z1 = (v[2] + v[6]) * FIX_0_541196100;
+    let tmp2 = z1 - v[6] * FIX_1_847759065;
+    let tmp3 = z1 + v[2] * FIX_0_765366865;
+    let tmp0 = (v[0] + v[4]) << CONST_BITS;
+    let tmp1 = (v[0] - v[4]) << CONST_BITS;
+    let tmp10 = tmp0 + tmp3;
+    let tmp13 = tmp0 - tmp3;
+    let tmp11 = tmp1 + tmp2;
+    let tmp12 = tmp1 - tmp2;
+    // odd part
+    let z1 = v[7] + v[1];
+    let z2 = v[5] + v[3];
+    let z3 = v[7] + v[3];
+    let z4 = v[5] + v[1];
+    let z5 = (z3 + z4) * FIX_1_175875602;
+    let z1 = -z1 * FIX_0_899976223;
+    let z2 = -z2 * FIX_2_562915447;
+    let z3 = -z3 * FIX_1_961570560 + z5;
+    let z4 = -z4 * FIX_0_390180644 + z5;
+    let tmp0 = v[7] * FIX_0_298631336 + z1 + z3;
+    let tmp1 = v[5] * FIX_2_053119869 + z2 + z4;
+    let tmp2 = v[3] * FIX_3_072711026 + z2 + z3;
+    let tmp3 = v[1] * FIX_1_501321110 + z1 + z4;
+    [
+        tmp10 + tmp3,
+        tmp11 + tmp2,
+        tmp12 + tmp1,
+        tmp13 + tmp0,
+        tmp13 - tmp0,
+        tmp12 - tmp1,
+        tmp11 - tmp2,
+        tmp10 - tmp3,
+    ]
+}
+
+// fixed-point IDCT, accurate to the float version within rounding
+pub fn idct_int(coeffs: &[i32; 64], precision: u8) -> [[u16; 8]; 8] {
+    // extra fraction bits kept between the passes, fewer for 12-bit samples as in libjpeg
+    let pass1_bits = if precision > 8 { 1 } else { 2 };
+    let block = natural_order(coeffs);
+    // columns
+    let mut workspace = [[0_i64; 8]; 8];
+    for x in 0..8 {
+        if (1..8).all(|y| block[y][x] == 0) {
+            // only the DC term, common enough to skip the multiplications
+            for row in workspace.iter_mut() {
+                row[x] = block[0][x] << pass1_bits;
+            }
+            continue;
+        }
+        let mut column = [0; 8];
+        for (v, row) in column.iter_mut().zip(block.iter()) {
+            *v = row[x];
+        }
+        for (row, v) in workspace.iter_mut().zip(idct_1d(&column).iter()) {
+            row[x] = descale(*v, CONST_BITS - pass1_bits);
+        }
+    }
+    // rows
+    let mut res = [[0_u16; 8]; 8];
+    for (res_row, row) in res.iter_mut().zip(workspace.iter()) {
+        for (r, v) in res_row.iter_mut().zip(idct_1d(row).iter()) {
+            *r = to_sample(descale(*v, CONST_BITS + pass1_bits + 3), precision);
+        }
+    }
+    res
+}
+
+// reference IDCT evaluating the cosine sums directly in floating point
+pub fn idct_float(coeffs: &[i32; 64], precision: u8) -> [[u16; 8]; 8] {
+    let mut zigzaged = [[0_f64; 8]; 8];
+    for (row, zigzag_row) in zigzaged.iter_mut().zip(ZIGZAGS.iter()) {
+        for (v, &z) in row.iter_mut().zip(zigzag_row.iter()) {
+            *v = coeffs[z as usize] as f64;
+        }
+    }
+    let mut sumx = [[0_f64; 8]; 8];
+    let s2 = f64::sqrt(2.);
+    for (jy, (sum_row, row)) in sumx.iter_mut().zip(zigzaged.iter()).enumerate() {
+        for (ix, sum) in sum_row.iter_mut().enumerate() {
+            let mut s: f64 = 0.;
+            for (jx, v) in row.iter().enumerate() {
+                let cy: f64 = if jy == 0 { 1. } else { s2 };
+                let cx: f64 = if jx == 0 { 1. } else { s2 };
+                s += cy
+                    * cx
+                    * (std::f64::consts::PI * ((2 * ix + 1) * jx) as f64 / ((2 * 8) as f64))
+                        .cos()
+                    * v;
+            }
+            *sum = s
+        }
+    }
+    let mut res = [[0_u16; 8]; 8];
+    for (iy, res_row) in res.iter_mut().enumerate() {
+        for (ix, v) in res_row.iter_mut().enumerate() {
+            let mut s: f64 = 0.;
+            for (jy, sum_row) in sumx.iter().enumerate() {
+                s += (std::f64::consts::PI * ((2 * iy + 1) * jy) as f64 / ((2 * 8) as f64))
+                    .cos()
+                    * sum_row[ix];
+            }
+            let mut r = ((s / 8.).round()) as i32 + (1 << (precision - 1));
+            r = i32::max(r, 0);
+            r = i32::min(r, (1 << precision) - 1);
+            *v = r as u16
+        }
+    }
+    res
+}
+
+// reduced 4 point IDCT of libjpeg's jidctred.c, scaled up by 2^(CONST_BITS + 1)
+fn idct_1d_4(v: &[i64; 8]) -> [i64; 4] {
+    let tmp0 = v[0] << (CONST_BITS + 1);
+    let tmp2 = v[2] * FIX_1_847759065 - v[6] * FIX_0_765366865;
+    let tmp10 = tmp0 + tmp2;
+    let tmp12 = tmp0 - tmp2;
+    let tmp0 = -v[7] * FIX_0_211164243 + v[5] * FIX_1_451774981 - v[3] * FIX_2_172734803
+        + v[1] * FIX_1_061594337;
+    let tmp2 = -v[7] * FIX_0_509795579 - v[5] * FIX_0_601344887 + v[3] * FIX_0_899976223
+        + v[1] * FIX_2_562915447;
+    [tmp10 + tmp2, tmp12 + tmp0, tmp12 - tmp0, tmp10 - tmp2]
+}
+
+// reduced 2 point IDCT of libjpeg's jidctred.c, scaled up by 2^(CONST_BITS + 2)
+fn idct_1d_2(v: &[i64; 8]) -> [i64; 2] {
+    let tmp10 = v[0] << (CONST_BITS + 2);
+    let tmp0 = -v[7] * FIX_0_720959822 + v[5] * FIX_0_850430095 - v[3] * FIX_1_272758580
+        + v[1] * FIX_3_624509785;
+    [tmp10 + tmp0, tmp10 - tmp0]
+}
+
+// the reduced IDCTs below only set the top left 4x4, 2x2 or 1x1 samples of the result
+pub fn idct_4x4(coeffs: &[i32; 64], precision: u8) -> [[u16; 8]; 8] {
+    let pass1_bits = if precision > 8 { 1 } else { 2 };
+    let block = natural_order(coeffs);
+    let mut workspace = [[0_i64; 8]; 4];
+    for x in 0..8 {
+        // column 4 does not contribute to the reduced output
+        if x == 4 {
+            continue;
+        }
+        let mut column = [0; 8];
+        for (v, row) in column.iter_mut().zip(block.iter()) {
+            *v = row[x];
+        }
+        for (row, v) in workspace.iter_mut().zip(idct_1d_4(&column).iter()) {
+            row[x] = descale(*v, CONST_BITS - pass1_bits + 1);
+        }
+    }
+    let mut res = [[0_u16; 8]; 8];
+    for (res_row, row) in res.iter_mut().zip(workspace.iter()) {
+        for (r, v) in res_row.iter_mut().zip(idct_1d_4(row).iter()) {
+            *r = to_sample(descale(*v, CONST_BITS + pass1_bits + 3 + 1), precision);
+        }
+    }
+    res
+}
+
+pub fn idct_2x2(coeffs: &[i32; 64], precision: u8) -> [[u16; 8]; 8] {
+    let pass1_bits = if precision > 8 { 1 } else { 2 };
+    let block = natural_order(coeffs);
+    let mut workspace = [[0_i64; 8]; 2];
+    // only the odd columns and column 0 contribute to the reduced output
+    for x in [0, 1, 3, 5, 7].iter().copied() {
+        let mut column = [0; 8];
+        for (v, row) in column.iter_mut().zip(block.iter()) {
+            *v = row[x];
+        }
+        for (row, v) in workspace.iter_mut().zip(idct_1d_2(&column).iter()) {
+            row[x] = descale(*v, CONST_BITS - pass1_bits + 2);
+        }
+    }
+    let mut res = [[0_u16; 8]; 8];
+    for (res_row, row) in res.iter_mut().zip(workspace.iter()) {
+        for (r, v) in res_row.iter_mut().zip(idct_1d_2(row).iter()) {
+            *r = to_sample(descale(*v, CONST_BITS + pass1_bits + 3 + 2), precision);
+        }
+    }
+    res
+}
+
+pub fn idct_1x1(coeffs: &[i32; 64], precision: u8) -> [[u16; 8]; 8] {
+    let mut res = [[0_u16; 8]; 8];
+    res[0][0] = to_sample(descale(coeffs[0] as i64, 3), precision);
+    res
+}
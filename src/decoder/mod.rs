@@ -1,26 +1,23 @@
+mod arith;
+mod error;
 mod haff;
+mod idct;
+mod parallel;
+mod push;
 
-use failure::format_err;
-use failure::Error;
+use arith::ArithConditioning;
+use arith::ArithDecoder;
+pub use error::{Error, ErrorKind, TableClass};
 use haff::HaffDecoder;
 use haff::HaffTable;
 use log::{info, warn};
+pub use push::{Progress, PushDecoder, PushInput};
 use std::io::{Cursor, Read, Write};
 use std::iter::Iterator;
+use std::ops::Range;
 
 type Result<T> = std::result::Result<T, Error>;
 
-static ZIGZAGS: [[i32; 8]; 8] = [
-    [0, 1, 5, 6, 14, 15, 27, 28],
-    [2, 4, 7, 13, 16, 26, 29, 42],
-    [3, 8, 12, 17, 25, 30, 41, 43],
-    [9, 11, 18, 24, 31, 40, 44, 53],
-    [10, 19, 23, 32, 39, 45, 52, 54],
-    [20, 22, 33, 38, 46, 51, 55, 60],
-    [21, 34, 37, 47, 50, 56, 59, 61],
-    [35, 36, 48, 49, 57, 58, 62, 63],
-];
-
 fn read_u8<T: Read>(r: &mut T) -> Result<u8> {
     let mut buf = [0; 1];
     r.read_exact(&mut buf)?;
@@ -32,25 +29,72 @@ fn read_u16<T: Read>(r: &mut T) -> Result<u16> {
     Ok((buf[0] as u16) * 0x100 + (buf[1] as u16))
 }
 
-fn tr255(i: f64) -> i32 {
-    i32::min(i32::max(i as i32, 0), 255)
+fn clamp_sample(i: f64, max: i32) -> i32 {
+    i32::min(i32::max(i as i32, 0), max)
+}
+// samples of other bit depths are scaled to 8 bits by shifting
+fn scale_to_u8(v: i32, precision: u8) -> u8 {
+    if precision >= 8 {
+        (v >> (precision - 8)) as u8
+    } else {
+        (v << (8 - precision)) as u8
+    }
 }
 fn ceildiv(d0: u64, d1: u64) -> u64 {
-    (d0 + (d1 - 1)) / d1
+    d0.div_ceil(d1)
+}
+
+// the plane holds samples already shifted left by the point transform pt
+#[allow(clippy::too_many_arguments)]
+fn predict_lossless(
+    plane: &[u16],
+    stride: usize,
+    x: usize,
+    y: usize,
+    first_line: bool,
+    predictor: u8,
+    initial: i32,
+    pt: u8,
+) -> i32 {
+    let sample = |offset: usize| (plane[offset] >> pt) as i32;
+    if first_line {
+        return if x == 0 { initial } else { sample(y * stride + x - 1) };
+    }
+    if x == 0 {
+        return sample((y - 1) * stride);
+    }
+    let ra = sample(y * stride + x - 1);
+    let rb = sample((y - 1) * stride + x);
+    let rc = sample((y - 1) * stride + x - 1);
+    match predictor {
+        1 => ra,
+        2 => rb,
+        3 => rc,
+        4 => ra + rb - rc,
+        5 => ra + ((rb - rc) >> 1),
+        6 => rb + ((ra - rc) >> 1),
+        _ => (ra + rb) / 2,
+    }
+}
+
+fn dequantize(table: &[u16; 64], coeffs: &mut [i32; 64]) {
+    for (c, &q) in coeffs.iter_mut().zip(table.iter()) {
+        *c *= q as i32;
+    }
 }
 
 fn check_soi<T: Read>(r: &mut T) -> Result<()> {
     let u0 = read_u8(r)?;
     let u1 = read_u8(r)?;
     if u0 != 0xff || u1 != 0xd8 {
-        return Err(format_err!("no SOI found found {} {}", u0, u1));
+        return Err(ErrorKind::NotJpeg.into());
     }
     Ok(())
 }
 
 struct QuantizationTable {
     id: u8,
-    table: [u8; 64],
+    table: [u16; 64],
 }
 
 struct ScanComponent {
@@ -60,6 +104,7 @@ struct ScanComponent {
     qt_id: u8,
 }
 
+#[derive(Clone)]
 struct Component {
     qt_id: u8,
     tdj: u8,
@@ -67,12 +112,302 @@ struct Component {
     hi: u8,
     vi: u8,
     prev_dc: i32,
-    plane: Vec<u8>,
+    plane: Vec<u16>,
     stride: i32,
+    // quantization table latched at the first scan of a progressive component
+    qt: Option<[u16; 64]>,
+}
+
+/// contents of a JFIF APP0 segment, unit is 0 for an aspect ratio only, 1 for dpi and 2 for dpc
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Jfif {
+    pub version: u16,
+    pub unit: u8,
+    pub x_density: u16,
+    pub y_density: u16,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorSpace {
+    Gray,
+    YCbCr,
+    // three components coded without color transform
+    Rgb,
+    Cmyk,
+    Ycck,
+}
+
+/// the conversion of YCbCr frames to RGB. JFIF images are Bt601 with full range samples, frames
+/// taken from video have luma from 16 to 235 and chroma from 16 to 240 (in 8 bits) and are
+/// Bt601Limited when they come from standard definition video and Bt709 from HD video
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorMatrix {
+    Bt601,
+    Bt601Limited,
+    Bt709,
+}
+
+impl ColorMatrix {
+    // the factors of cr for red, cb and cr subtracted for green and cb for blue
+    fn coefficients(self) -> (f64, f64, f64, f64) {
+        match self {
+            ColorMatrix::Bt601 | ColorMatrix::Bt601Limited => (1.402, 0.34414, 0.71414, 1.772),
+            ColorMatrix::Bt709 => (1.5748, 0.18732, 0.46812, 1.8556),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IdctMethod {
+    Integer,
+    Float,
+}
+
+/// output size relative to the frame, done with reduced IDCTs
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Scale {
+    Full,
+    Half,
+    Quarter,
+    Eighth,
+}
+
+impl Scale {
+    fn denominator(self) -> u16 {
+        match self {
+            Scale::Full => 1,
+            Scale::Half => 2,
+            Scale::Quarter => 4,
+            Scale::Eighth => 8,
+        }
+    }
+}
+
+/// how components with fewer samples than the image are brought to its size. Fast repeats every
+/// sample, Fancy interpolates between them as libjpeg does for components at half the horizontal,
+/// the vertical or both rates, and repeats the samples of the others
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Upsampling {
+    Fast,
+    Fancy,
+}
+
+// the libjpeg filter a component is upsampled with, named by the ratio of the largest sampling
+// factors to its own
+#[derive(Clone, Copy, PartialEq)]
+enum Filter {
+    Replicate,
+    H2V1,
+    H1V2,
+    H2V2,
+}
+
+// the sample at pixel (x, y) of the scaled image of a component upsampled by a triangle filter,
+// where the nearer of two samples weighs 3/4. Samples past the width by height samples of the
+// component repeat the edge ones, the plane starts at sample row first
+fn upsample(c: &Component, filter: Filter, x: i32, y: i32, (width, height): (i32, i32), first: i32) -> i32 {
+    let sample =
+        |sx: i32, sy: i32| c.plane[((sy.clamp(0, height - 1) - first) * c.stride + sx.clamp(0, width - 1)) as usize] as i32;
+    // the rounding bias alternates between the two pixels of a sample so that it does not drift
+    if filter == Filter::H2V1 {
+        return if x % 2 == 0 {
+            (3 * sample(x / 2, y) + sample(x / 2 - 1, y) + 1) >> 2
+        } else {
+            (3 * sample(x / 2, y) + sample(x / 2 + 1, y) + 2) >> 2
+        };
+    }
+    let (sy, bias) = (y / 2, 1 + y % 2);
+    let near = if y % 2 == 0 { sy - 1 } else { sy + 1 };
+    if filter == Filter::H1V2 {
+        return (3 * sample(x, sy) + sample(x, near) + bias) >> 2;
+    }
+    let column = |sx: i32| 3 * sample(sx, sy) + sample(sx, near);
+    if x % 2 == 0 {
+        (3 * column(x / 2) + column(x / 2 - 1) + 8) >> 4
+    } else {
+        (3 * column(x / 2) + column(x / 2 + 1) + 7) >> 4
+    }
+}
+
+#[derive(Clone)]
+enum EntropyDecoder {
+    Haff(HaffDecoder),
+    Arith(Box<ArithDecoder>),
+}
+
+impl EntropyDecoder {
+    fn reset(&mut self) {
+        match self {
+            EntropyDecoder::Haff(d) => d.reset(),
+            EntropyDecoder::Arith(d) => d.reset(),
+        }
+    }
+    fn take_marker(&mut self) -> Option<u8> {
+        match self {
+            EntropyDecoder::Haff(d) => d.take_marker(),
+            EntropyDecoder::Arith(d) => d.take_marker(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ScanKind {
+    Sequential,
+    // ss, se, ah, al
+    Progressive(u8, u8, u8, u8),
+    // predictor, point transform
+    Lossless(u8, u8),
+}
+
+// a scan being decoded, kept by the decoder while a push decode waits for more data
+#[derive(Clone)]
+struct Scan {
+    kind: ScanKind,
+    decoder: EntropyDecoder,
+    components: Vec<Component>,
+    frame_indices: Vec<usize>,
+    scan_x: u64,
+    scan_y: u64,
+    mcu_ptr: u64,
+    // MCU row where the current restart interval started
+    first_line: u64,
+}
+
+// a BlockDecoder of the fields of a decoder, borrowed one by one so that the reader can be borrowed
+// along with it
+macro_rules! block_decoder {
+    ($decoder:expr) => {
+        BlockDecoder {
+            qts: &$decoder.qts,
+            hafftables: &$decoder.hafftables,
+            arithmetic: $decoder.arithmetic,
+            arith_conditioning: $decoder.arith_conditioning,
+            idct_method: $decoder.idct_method,
+            scale: $decoder.scale,
+            precision: $decoder.precision,
+            region: &$decoder.region,
+        }
+    };
+}
+
+// the decoder state that decoding the blocks of a sequential scan reads, borrowed apart from the
+// reader so that threads decoding restart intervals in parallel can share it
+struct BlockDecoder<'a> {
+    qts: &'a [QuantizationTable],
+    hafftables: &'a [HaffTable],
+    arithmetic: bool,
+    arith_conditioning: ArithConditioning,
+    idct_method: IdctMethod,
+    scale: Scale,
+    precision: u8,
+    region: &'a [(Range<u64>, Range<u64>)],
+}
+
+impl BlockDecoder<'_> {
+    fn new_entropy_decoder(&self) -> EntropyDecoder {
+        if self.arithmetic {
+            EntropyDecoder::Arith(Box::new(ArithDecoder::new(self.arith_conditioning)))
+        } else {
+            EntropyDecoder::Haff(HaffDecoder::new())
+        }
+    }
+    fn idct(&self, coeffs: &[i32; 64]) -> [[u16; 8]; 8] {
+        match (self.scale, self.idct_method) {
+            (Scale::Full, IdctMethod::Integer) => idct::idct_int(coeffs, self.precision),
+            (Scale::Full, IdctMethod::Float) => idct::idct_float(coeffs, self.precision),
+            (Scale::Half, _) => idct::idct_4x4(coeffs, self.precision),
+            (Scale::Quarter, _) => idct::idct_2x2(coeffs, self.precision),
+            (Scale::Eighth, _) => idct::idct_1x1(coeffs, self.precision),
+        }
+    }
+    fn quantization_table(&self, qt_id: u8) -> Result<[u16; 64]> {
+        let q_table = self
+            .qts
+            .iter()
+            .find(|&qt| qt_id == qt.id)
+            .ok_or(ErrorKind::MissingTable { class: TableClass::Quantization, id: qt_id })?;
+        Ok(q_table.table)
+    }
+    // whether the crop region needs the block at column bx and row by of a frame component
+    fn in_region(&self, frame_index: usize, bx: u64, by: u64) -> bool {
+        match self.region.get(frame_index) {
+            Some((columns, rows)) => columns.contains(&bx) && rows.contains(&by),
+            None => true,
+        }
+    }
+    // the coefficients of a block, with the DC difference still to be added to the previous DC
+    fn parse_coeffs<R: Read>(
+        &self,
+        reader: &mut R,
+        decoder: &mut EntropyDecoder,
+        index: usize,
+        tdj: u8,
+        taj: u8,
+    ) -> Result<[i32; 64]> {
+        match decoder {
+            EntropyDecoder::Haff(decoder) => {
+                let ac_haff = self
+                    .hafftables
+                    .iter()
+                    .find(|&ht| taj == ht.id && ht.tc != 0)
+                    .ok_or(ErrorKind::MissingTable { class: TableClass::HuffmanAc, id: taj })?;
+                let dc_haff = self
+                    .hafftables
+                    .iter()
+                    .find(|&ht| tdj == ht.id && ht.tc == 0)
+                    .ok_or(ErrorKind::MissingTable { class: TableClass::HuffmanDc, id: tdj })?;
+                decoder.parse_coeffs(reader, dc_haff, ac_haff)
+            }
+            EntropyDecoder::Arith(decoder) => decoder.parse_coeffs(reader, index, tdj, taj),
+        }
+    }
+    fn render_block(&self, qt_id: u8, mut coeffs: [i32; 64]) -> Result<[[u16; 8]; 8]> {
+        dequantize(&self.quantization_table(qt_id)?, &mut coeffs);
+        Ok(self.idct(&coeffs))
+    }
+}
+
+// keeps track of the number of bytes read so that errors can point into the input
+struct CountingReader<T: Read> {
+    inner: T,
+    position: u64,
 }
 
+impl<T: Read> Read for CountingReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// errors a lenient decode went past, in input order, and how many MCUs of the scans that were
+/// started could be decoded
+#[derive(Debug)]
+pub struct Recovery {
+    pub errors: Vec<Error>,
+    pub mcus_recovered: u64,
+    pub mcus_total: u64,
+}
+
+/// the samples of a component at its own sampling rate, with the precision of the frame. Row y
+/// starts at samples[y * stride] and its first width samples are part of the image
+pub struct Plane<'a> {
+    pub samples: &'a [u16],
+    pub stride: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Plane<'_> {
+    pub fn row(&self, y: usize) -> &[u16] {
+        &self.samples[y * self.stride..y * self.stride + self.width]
+    }
+}
+
+/// decodes a JPEG image from a reader, the headers and the image are read by decode or next_rows
 pub struct Decoder<T: Read> {
-    reader: T,
+    reader: CountingReader<T>,
     qts: Vec<QuantizationTable>,
     hafftables: Vec<HaffTable>,
     scan_components: Vec<ScanComponent>,
@@ -80,12 +415,54 @@ pub struct Decoder<T: Read> {
     width: u16,
     components: Vec<Component>,
     restart_interval: u16,
+    progressive: bool,
+    lossless: bool,
+    precision: u8,
+    coeffs: Vec<Vec<[i32; 64]>>,
+    arithmetic: bool,
+    arith_conditioning: ArithConditioning,
+    unread_marker: Option<u8>,
+    adobe_transform: Option<u8>,
+    idct_method: IdctMethod,
+    scale: Scale,
+    upsampling: Upsampling,
+    color_matrix: ColorMatrix,
+    jfif: Option<Jfif>,
+    // x, y, width and height in pixels of the scaled image
+    crop: Option<(u16, u16, u16, u16)>,
+    // columns and rows of blocks of every frame component that the crop rectangle covers
+    region: Vec<(Range<u64>, Range<u64>)>,
+    lenient: bool,
+    threads: usize,
+    // MCU grid of the last scan that started decoding
+    last_scan: Option<(u64, u64)>,
+    mcus_total: u64,
+    mcus_lost: u64,
+    errors: Vec<Error>,
+    recovery: Option<Recovery>,
+    scan: Option<Scan>,
+    scans_complete: usize,
+    // set for a PushDecoder, which returns to the checkpoint when the input runs out
+    push: bool,
+    checkpoint: Option<push::Checkpoint>,
+    // decode_band was called, then the planes of a sequential frame whose first scan has every
+    // component are banded, holding only the MCU row band_row
+    by_rows: bool,
+    banded: bool,
+    band_row: u64,
+    // 1 when fancy upsampling needs the sample rows next to the band, then the planes also hold the
+    // last sample row before it and the MCU row after it, which has to be decoded to complete it
+    band_context: u64,
+    // the band is complete, the next call to decode_band moves on to the next MCU row
+    band_full: bool,
+    decoded: bool,
+    rows_done: bool,
 }
 
 impl<T: Read> Decoder<T> {
     pub fn new(reader: T) -> Decoder<T> {
         Decoder {
-            reader: reader,
+            reader: CountingReader { inner: reader, position: 0 },
             qts: Vec::new(),
             hafftables: Vec::new(),
             height: 0,
@@ -93,9 +470,126 @@ impl<T: Read> Decoder<T> {
             scan_components: Vec::new(),
             components: Vec::new(),
             restart_interval: 0,
+            progressive: false,
+            lossless: false,
+            precision: 8,
+            coeffs: Vec::new(),
+            arithmetic: false,
+            arith_conditioning: ArithConditioning::new(),
+            unread_marker: None,
+            adobe_transform: None,
+            idct_method: IdctMethod::Integer,
+            scale: Scale::Full,
+            upsampling: Upsampling::Fast,
+            color_matrix: ColorMatrix::Bt601,
+            jfif: None,
+            crop: None,
+            region: Vec::new(),
+            lenient: false,
+            threads: 1,
+            last_scan: None,
+            mcus_total: 0,
+            mcus_lost: 0,
+            errors: Vec::new(),
+            recovery: None,
+            scan: None,
+            scans_complete: 0,
+            push: false,
+            checkpoint: None,
+            by_rows: false,
+            banded: false,
+            band_row: 0,
+            band_context: 0,
+            band_full: false,
+            decoded: false,
+            rows_done: false,
         }
     }
+    pub fn set_idct_method(&mut self, idct_method: IdctMethod) {
+        self.idct_method = idct_method;
+    }
+    /// must be set before decoding, lossless frames are always decoded at full size
+    pub fn set_scale(&mut self, scale: Scale) {
+        self.scale = scale;
+    }
+    /// must be set before decoding
+    pub fn set_upsampling(&mut self, upsampling: Upsampling) {
+        self.upsampling = upsampling;
+    }
+    /// how YCbCr frames are converted to RGB, Bt601 by default as in JFIF
+    pub fn set_color_matrix(&mut self, color_matrix: ColorMatrix) {
+        self.color_matrix = color_matrix;
+    }
+    /// must be set before decoding, only the part of the image in the rectangle is transformed and
+    /// converted. It is given in pixels of the scaled image and clipped to it
+    pub fn set_crop(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        self.crop = Some((x, y, width, height));
+    }
+    /// in lenient mode an error after the frame header ends decoding without failing, or if the scan
+    /// has restart intervals, decoding resumes at the next restart marker. The image keeps what was
+    /// decoded and get_recovery reports the errors
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.lenient = lenient;
+    }
+    /// sequential scans with restart intervals are decoded on up to this many threads, other scans,
+    /// scans decoded by rows and wasm builds always decode on the calling thread
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+    fn scale_denominator(&self) -> u16 {
+        if self.lossless {
+            1
+        } else {
+            self.scale.denominator()
+        }
+    }
+    // size of a decoded block edge
+    fn block_size(&self) -> usize {
+        8 / self.scale_denominator() as usize
+    }
+    fn output_pixels(&self) -> usize {
+        let (width, height) = self.output_size();
+        width as usize * height as usize
+    }
+    // the filter of a component with the sampling factors hi and vi. Like libjpeg, images scaled
+    // to 1/8 are not filtered, nor horizontally components two samples wide or less
+    fn filter(&self, hi: u8, vi: u8) -> Filter {
+        if self.upsampling == Upsampling::Fast || self.scale_denominator() == 8 {
+            return Filter::Replicate;
+        }
+        let (max_hi, max_vi) = self.max_sampling();
+        let width = ceildiv(self.scaled_size().0 as u64 * hi as u64, max_hi as u64);
+        let ratio = |max: u8, factor: u8| if max == factor { 1 } else if max == 2 * factor { 2 } else { 0 };
+        match (ratio(max_hi, hi), ratio(max_vi, vi)) {
+            (2, 1) if width > 2 => Filter::H2V1,
+            (2, 2) if width > 2 => Filter::H2V2,
+            (1, 2) => Filter::H1V2,
+            _ => Filter::Replicate,
+        }
+    }
+    fn scaled_size(&self) -> (u16, u16) {
+        let denominator = self.scale_denominator();
+        (ceildiv(self.width as u64, denominator as u64) as u16, ceildiv(self.height as u64, denominator as u64) as u16)
+    }
+    // (x, y, width, height) of the output in the scaled image
+    fn crop_rect(&self) -> (u16, u16, u16, u16) {
+        let (width, height) = self.scaled_size();
+        match self.crop {
+            Some((x, y, w, h)) => {
+                let (x, y) = (x.min(width), y.min(height));
+                (x, y, w.min(width - x), h.min(height - y))
+            }
+            None => (0, 0, width, height),
+        }
+    }
+    fn output_size(&self) -> (u16, u16) {
+        let (_, _, width, height) = self.crop_rect();
+        (width, height)
+    }
     fn next_marker(&mut self) -> Result<u8> {
+        if let Some(m) = self.unread_marker.take() {
+            return Ok(m);
+        }
         let mut ignored = 0;
         loop {
             let u0 = read_u8(&mut self.reader)?;
@@ -114,6 +608,9 @@ impl<T: Read> Decoder<T> {
     }
     fn read_marker_content(&mut self) -> Result<Vec<u8>> {
         let size = read_u16(&mut self.reader)?;
+        if size < 2 {
+            return Err(ErrorKind::InvalidSegment(format!("invalid segment length {}", size)).into());
+        }
         let mut buf = vec![0; size as usize - 2];
         self.reader.read_exact(&mut buf)?;
         Ok(buf)
@@ -124,8 +621,8 @@ impl<T: Read> Decoder<T> {
         let mut cursor = Cursor::new(content);
         let mut prefix = [0; 5];
         cursor.read_exact(&mut prefix)?;
-        match std::str::from_utf8(&prefix)? {
-            "JFIF\0" => {
+        match &prefix {
+            b"JFIF\0" => {
                 info!("JFIF APP0");
                 let version = read_u16(&mut cursor)?;
                 let unit = read_u8(&mut cursor)?;
@@ -147,12 +644,45 @@ impl<T: Read> Decoder<T> {
                     ydensity
                 );
                 info!("xhtumnail={} ythumbnail={}", xthumbnail, ythumbnail);
+                self.jfif = Some(Jfif {
+                    version,
+                    unit,
+                    x_density: xdensity,
+                    y_density: ydensity,
+                });
             }
-            "JFXX\0" => info!("JFXX APP0"),
+            b"JFXX\0" => info!("JFXX APP0"),
             _ => (),
         }
         Ok(())
     }
+    fn parse_app14(&mut self) -> Result<()> {
+        let content = self.read_marker_content()?;
+        info!("APP14 size={}", content.len());
+        if content.len() < 12 || &content[0..5] != b"Adobe" {
+            return Ok(());
+        }
+        let mut cursor = Cursor::new(&content[5..]);
+        let version = read_u16(&mut cursor)?;
+        let flags0 = read_u16(&mut cursor)?;
+        let flags1 = read_u16(&mut cursor)?;
+        let transform = read_u8(&mut cursor)?;
+        info!(
+            "Adobe APP14 version={} flags0={:x} flags1={:x} transform={}({})",
+            version,
+            flags0,
+            flags1,
+            transform,
+            match transform {
+                0 => "RGB or CMYK",
+                1 => "YCbCr",
+                2 => "YCCK",
+                _ => "?",
+            }
+        );
+        self.adobe_transform = Some(transform);
+        Ok(())
+    }
     fn parse_app(&mut self, index: u8) -> Result<()> {
         let content = self.read_marker_content()?;
         info!("APP{} size={}", index, content.len());
@@ -168,15 +698,38 @@ impl<T: Read> Decoder<T> {
             let pq = flag >> 4;
             let tq = flag & 0xf;
             info!("pq(presision)={} tq(destination identifier)={}", pq, tq);
-            let mut buf = [0; 64];
-            cursor.read_exact(&mut buf)?;
-            self.qts.push(QuantizationTable { id: tq, table: buf })
+            let mut table = [0; 64];
+            match pq {
+                0 => {
+                    let mut buf = [0; 64];
+                    cursor.read_exact(&mut buf)?;
+                    for (q, &v) in table.iter_mut().zip(buf.iter()) {
+                        *q = v as u16;
+                    }
+                }
+                1 => {
+                    for q in table.iter_mut() {
+                        *q = read_u16(&mut cursor)?;
+                    }
+                }
+                _ => return Err(ErrorKind::InvalidSegment(format!("invalid DQT precision {}", pq)).into()),
+            }
+            // a table redefines the one with the same destination
+            self.qts.retain(|qt| qt.id != tq);
+            self.qts.push(QuantizationTable { id: tq, table })
         }
         Ok(())
     }
-    fn parse_sof0(&mut self) -> Result<()> {
+    fn parse_sof(&mut self, index: u8) -> Result<()> {
         let content = self.read_marker_content()?;
-        info!("SOF0 size={}", content.len());
+        info!("SOF{} size={}", index, content.len());
+        // only hierarchical files have more than one frame, and those are not supported
+        if !self.scan_components.is_empty() {
+            return Err(ErrorKind::InvalidSegment(format!("second frame header SOF{}", index)).into());
+        }
+        self.arithmetic = index >= 8;
+        self.progressive = index % 8 == 2;
+        self.lossless = index % 8 == 3;
         let mut r = Cursor::new(content);
         let p = read_u8(&mut r)?;
         let y = read_u16(&mut r)?;
@@ -186,6 +739,16 @@ impl<T: Read> Decoder<T> {
             "p(presision)={} y(lines)={} x(samples per line)={} nf(number of components)={}",
             p, y, x, nf
         );
+        // baseline frames are 8-bit only, extended and progressive ones may have 12-bit samples
+        // and lossless ones anything from 2 to 16 bits
+        let supported = if self.lossless { (2..=16).contains(&p) } else { p == 8 || (p == 12 && index != 0) };
+        if !supported {
+            return Err(ErrorKind::UnsupportedPrecision { sof: index, precision: p }.into());
+        }
+        if nf != 1 && nf != 3 && nf != 4 {
+            return Err(ErrorKind::UnsupportedComponentCount(nf).into());
+        }
+        self.precision = p;
         self.height = y;
         self.width = x;
         for _i in 0..nf {
@@ -198,15 +761,122 @@ impl<T: Read> Decoder<T> {
                 "ci(id)={} hi,vi(sampling factor)={},{} tqi(dqt selector)={}",
                 ci, hi, vi, tqi
             );
+            // any factors from 1 to 4 are allowed, the largest ones need not belong to the first
+            // component and need not be multiples of the others
+            if !(1..=4).contains(&hi) || !(1..=4).contains(&vi) {
+                return Err(
+                    ErrorKind::InvalidSegment(format!("invalid sampling factors {}x{} of component {}", hi, vi, ci)).into()
+                );
+            }
             self.scan_components.push(ScanComponent {
                 id: ci,
-                hi: hi,
-                vi: vi,
+                hi,
+                vi,
                 qt_id: tqi,
             })
         }
+        if self.progressive {
+            // coefficients of progressive frames are accumulated over scans before IDCT
+            let (mcu_x, mcu_y) = self.mcu_size();
+            self.coeffs = self
+                .scan_components
+                .iter()
+                .map(|sc| {
+                    let blocks = mcu_x * sc.hi as u64 * mcu_y * sc.vi as u64;
+                    vec![[0; 64]; blocks as usize]
+                })
+                .collect();
+        }
+        // every scan decodes into the planes of the frame components, which are padded to whole MCUs
+        // (lossless scans write one sample per data unit, the others a block of block_size squared)
+        self.banded = self.by_rows && !self.progressive && !self.lossless;
+        let vertical = |sc: &ScanComponent| matches!(self.filter(sc.hi, sc.vi), Filter::H1V2 | Filter::H2V2);
+        self.band_context = (self.banded && self.scan_components.iter().any(vertical)) as u64;
+        let (mcu_x, mcu_y, unit) = if self.lossless {
+            let (max_hi, max_vi) = self.max_sampling();
+            (ceildiv(self.width as u64, max_hi as u64), ceildiv(self.height as u64, max_vi as u64), 1)
+        } else {
+            let (mcu_x, mcu_y) = self.mcu_size();
+            (mcu_x, mcu_y, self.block_size() as u64)
+        };
+        if self.crop.is_some() && !self.lossless {
+            let (x, y, width, height) = self.crop_rect();
+            let (max_hi, max_vi) = self.max_sampling();
+            let block_size = self.block_size() as u64;
+            // the blocks holding the samples the first and the last pixel are upsampled from, and
+            // those next to them that fancy upsampling filters with
+            let margin = (self.upsampling == Upsampling::Fancy) as u64;
+            let blocks = |start: u16, len: u16, factor: u8, max: u8| {
+                if len == 0 {
+                    return 0..0;
+                }
+                let first = (start as u64 * factor as u64 / max as u64).saturating_sub(margin);
+                let last = (start as u64 + len as u64 - 1) * factor as u64 / max as u64 + margin;
+                first / block_size..last / block_size + 1
+            };
+            self.region = self
+                .scan_components
+                .iter()
+                .map(|sc| (blocks(x, width, sc.hi, max_hi), blocks(y, height, sc.vi, max_vi)))
+                .collect();
+        }
+        // samples no scan reaches stay at the neutral value, which is gray for YCbCr
+        let neutral = 1 << (self.precision - 1);
+        self.components = self
+            .scan_components
+            .iter()
+            .map(|sc| {
+                let stride = mcu_x * unit * sc.hi as u64;
+                let height = if self.banded {
+                    (1 + self.band_context) * unit * sc.vi as u64 + self.band_context
+                } else {
+                    mcu_y * unit * sc.vi as u64
+                };
+                Component {
+                    qt_id: sc.qt_id,
+                    tdj: 0,
+                    taj: 0,
+                    hi: sc.hi,
+                    vi: sc.vi,
+                    prev_dc: 0,
+                    plane: vec![neutral; (stride * height) as usize],
+                    stride: stride as i32,
+                    qt: None,
+                }
+            })
+            .collect();
         Ok(())
     }
+    fn max_sampling(&self) -> (u8, u8) {
+        let max_hi = self.scan_components.iter().map(|sc| sc.hi).max().unwrap_or(1);
+        let max_vi = self.scan_components.iter().map(|sc| sc.vi).max().unwrap_or(1);
+        (max_hi, max_vi)
+    }
+    fn mcu_size(&self) -> (u64, u64) {
+        let (max_hi, max_vi) = self.max_sampling();
+        (
+            ceildiv(self.width as u64, max_hi as u64 * 8),
+            ceildiv(self.height as u64, max_vi as u64 * 8),
+        )
+    }
+    // size of a scan in MCUs, where a scan with only one component is not interleaved and covers just
+    // the data units of that component, one per MCU. Data units are blocks of 8x8 samples, or single
+    // samples in lossless frames
+    fn scan_size(&self, components: &[Component]) -> (u64, u64) {
+        let unit = if self.lossless { 1 } else { 8 };
+        let (max_hi, max_vi) = self.max_sampling();
+        if components.len() != 1 {
+            return (
+                ceildiv(self.width as u64, max_hi as u64 * unit),
+                ceildiv(self.height as u64, max_vi as u64 * unit),
+            );
+        }
+        let c = &components[0];
+        (
+            ceildiv(ceildiv(self.width as u64 * c.hi as u64, max_hi as u64), unit),
+            ceildiv(ceildiv(self.height as u64 * c.vi as u64, max_vi as u64), unit),
+        )
+    }
     fn parse_dht(&mut self) -> Result<()> {
         let content = self.read_marker_content()?;
         let len = content.len() as u64;
@@ -224,14 +894,16 @@ impl<T: Read> Decoder<T> {
             );
             let mut bits = [0; 16];
             cursor.read_exact(&mut bits)?;
-            let valuenum = bits.iter().fold(0, |acc, a| acc + a);
-            let mut tmp_values = vec![0; valuenum as usize];
-            cursor.read_exact(&mut tmp_values)?;
-            let mut values = [0; 256];
-            for i in 0..valuenum {
-                values[i as usize] = tmp_values[i as usize];
+            let valuenum = bits.iter().map(|&n| n as usize).sum::<usize>();
+            if valuenum > 256 {
+                return Err(ErrorKind::InvalidSegment(format!("invalid DHT with {} values", valuenum)).into());
             }
-            self.hafftables.push(HaffTable::new(tc, tn, bits, values))
+            let mut values = [0; 256];
+            cursor.read_exact(&mut values[..valuenum])?;
+            let table = HaffTable::new(tc, tn, bits, values)?;
+            // a table redefines the one of the same class (DC or AC) and destination
+            self.hafftables.retain(|ht| ht.id != tn || (ht.tc == 0) != (tc == 0));
+            self.hafftables.push(table)
         }
         Ok(())
     }
@@ -244,80 +916,214 @@ impl<T: Read> Decoder<T> {
         info!("DRI size={} ri={}", len, ri);
         Ok(())
     }
-    fn idct(&mut self, coeffs: &[i32; 64]) -> [[u8; 8]; 8] {
-        let mut zigzaged = [[0 as f64; 8]; 8];
-        for iy in 0..8 {
-            for ix in 0..8 {
-                zigzaged[iy][ix] = coeffs[ZIGZAGS[iy][ix] as usize] as f64;
-            }
-        }
-        let mut sumx = [[0 as f64; 8]; 8];
-        let s2 = f64::sqrt(2.);
-        for jy in 0..8 {
-            for ix in 0..8 {
-                let mut s: f64 = 0.;
-                for jx in 0..8 {
-                    let cy: f64 = if jy == 0 { 1. } else { s2 };
-                    let cx: f64 = if jx == 0 { 1. } else { s2 };
-                    s += cy
-                        * cx
-                        * (std::f64::consts::PI * ((2 * ix + 1) * jx) as f64 / ((2 * 8) as f64))
-                            .cos()
-                        * zigzaged[jy][jx];
-                }
-                sumx[jy][ix] = s
+    fn parse_dac(&mut self) -> Result<()> {
+        let content = self.read_marker_content()?;
+        let len = content.len() as u64;
+        info!("DAC size={}", len);
+        let mut cursor = Cursor::new(content);
+        while len > cursor.position() {
+            let flag = read_u8(&mut cursor)?;
+            let tc = flag >> 4;
+            let tb = flag & 0xf;
+            let cs = read_u8(&mut cursor)?;
+            info!("tc={} tb(destination identifier)={} cs(conditioning table value)={}", tc, tb, cs);
+            if tb >= 4 {
+                return Err(ErrorKind::InvalidSegment(format!("invalid DAC destination {}", tb)).into());
             }
-        }
-        let mut res = [[0 as u8; 8]; 8];
-        for iy in 0..8 {
-            for ix in 0..8 {
-                let mut s: f64 = 0.;
-                for jy in 0..8 {
-                    s += (std::f64::consts::PI * ((2 * iy + 1) * jy) as f64 / ((2 * 8) as f64))
-                        .cos()
-                        * sumx[jy][ix];
+            let c = &mut self.arith_conditioning;
+            if tc == 0 {
+                let (l, u) = (cs & 0xf, cs >> 4);
+                if l > u {
+                    return Err(ErrorKind::InvalidSegment(format!("invalid DAC conditioning L={} U={}", l, u)).into());
+                }
+                c.dc_l[tb as usize] = l;
+                c.dc_u[tb as usize] = u;
+            } else {
+                if !(1..=63).contains(&cs) {
+                    return Err(ErrorKind::InvalidSegment(format!("invalid DAC conditioning K={}", cs)).into());
                 }
-                let mut r = ((s / 8.).round()) as i32 + 128;
-                r = i32::max(r, 0);
-                r = i32::min(r, 255);
-                res[iy][ix] = r as u8
+                c.ac_k[tb as usize] = cs;
             }
         }
-        res
+        Ok(())
+    }
+    fn blocks(&self) -> BlockDecoder<'_> {
+        block_decoder!(self)
     }
-    fn parse_block(
+    // the reader borrowed along with the tables, for decoding blocks from it
+    fn blocks_and_reader(&mut self) -> (BlockDecoder<'_>, &mut CountingReader<T>) {
+        (block_decoder!(self), &mut self.reader)
+    }
+    fn new_entropy_decoder(&self) -> EntropyDecoder {
+        self.blocks().new_entropy_decoder()
+    }
+    fn idct(&self, coeffs: &[i32; 64]) -> [[u16; 8]; 8] {
+        self.blocks().idct(coeffs)
+    }
+    fn quantization_table(&self, qt_id: u8) -> Result<[u16; 64]> {
+        self.blocks().quantization_table(qt_id)
+    }
+    fn in_region(&self, frame_index: usize, bx: u64, by: u64) -> bool {
+        self.blocks().in_region(frame_index, bx, by)
+    }
+    // copies a decoded block to the block column bx and row by of a frame component
+    fn store_block(&mut self, frame_index: usize, bx: u64, by: u64, block: &[[u16; 8]; 8]) {
+        let block_size = self.block_size();
+        let c = &mut self.components[frame_index];
+        let offset_x = bx as usize * block_size;
+        let offset_y = (by - self.band_row * c.vi as u64) as usize * block_size + self.band_context as usize;
+        for (iy, row) in block.iter().take(block_size).enumerate() {
+            let offset = offset_x + (offset_y + iy) * c.stride as usize;
+            c.plane[offset..offset + block_size].copy_from_slice(&row[..block_size]);
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    fn parse_progressive_block(
         &mut self,
-        decoder: &mut HaffDecoder,
-        qt_id: u8,
-        tdj: u8,
-        taj: u8,
-        prev_dc: i32,
-    ) -> Result<(i32, [[u8; 8]; 8])> {
+        decoder: &mut EntropyDecoder,
+        index: usize,
+        frame_index: usize,
+        block_index: usize,
+        component: &mut Component,
+        ss: u8,
+        se: u8,
+        ah: u8,
+        al: u8,
+    ) -> Result<()> {
+        let coeffs = &mut self.coeffs[frame_index][block_index];
+        let decoder = match decoder {
+            EntropyDecoder::Haff(decoder) => decoder,
+            EntropyDecoder::Arith(decoder) => {
+                if ss == 0 {
+                    if ah != 0 {
+                        return decoder.refine_dc(&mut self.reader, coeffs, al);
+                    }
+                    component.prev_dc += decoder.parse_dc_diff(&mut self.reader, index, component.tdj)?;
+                    coeffs[0] = component.prev_dc * (1 << al);
+                    return Ok(());
+                }
+                return if ah == 0 {
+                    decoder.parse_ac_first(&mut self.reader, component.taj, coeffs, ss, se, al)
+                } else {
+                    decoder.parse_ac_refine(&mut self.reader, component.taj, coeffs, ss, se, al)
+                };
+            }
+        };
+        if ss == 0 {
+            if ah != 0 {
+                return decoder.refine_dc(&mut self.reader, coeffs, al);
+            }
+            let dc_haff = self
+                .hafftables
+                .iter()
+                .find(|&ht| component.tdj == ht.id && ht.tc == 0)
+                .ok_or(ErrorKind::MissingTable { class: TableClass::HuffmanDc, id: component.tdj })?;
+            component.prev_dc += decoder.parse_dc_diff(&mut self.reader, dc_haff)?;
+            coeffs[0] = component.prev_dc * (1 << al);
+            return Ok(());
+        }
         let ac_haff = self
             .hafftables
             .iter()
-            .find(|&ht| taj == ht.id && ht.tc != 0)
-            .ok_or(format_err!("cannot found ac_hafftable"))?;
-        let dc_haff = self
-            .hafftables
-            .iter()
-            .find(|&ht| tdj == ht.id && ht.tc == 0)
-            .ok_or(format_err!("cannot found dc_hafftable"))?;
-        let q_table = self
-            .qts
-            .iter()
-            .find(|&qt| qt_id == qt.id)
-            .ok_or(format_err!("cannot found q_table"))?;
-        let mut coeffs = decoder.parse_coeffs(&mut self.reader, dc_haff, ac_haff)?;
-        coeffs[0] += prev_dc;
-        let cur_dc = coeffs[0];
-        for i in 0..64 {
-            coeffs[i] = coeffs[i] * (q_table.table[i] as i32)
-        }
-        //for i in 0..64 {print!("{},", coeffs[i]);};info!("");
-        let idcted = self.idct(&coeffs);
-        //info!("{:?}", idcted);
-        Ok((cur_dc, idcted))
+            .find(|&ht| component.taj == ht.id && ht.tc != 0)
+            .ok_or(ErrorKind::MissingTable { class: TableClass::HuffmanAc, id: component.taj })?;
+        if ah == 0 {
+            decoder.parse_ac_first(&mut self.reader, ac_haff, coeffs, ss, se, al)
+        } else {
+            decoder.parse_ac_refine(&mut self.reader, ac_haff, coeffs, ss, se, al)
+        }
+    }
+    // the caller resets its entropy decoder once the RST marker has been checked
+    fn read_restart(&mut self, mcu_ptr: u64, ix: u64, iy: u64) -> Result<()> {
+        let next_marker = self.next_marker()?;
+        let expected = ((mcu_ptr / (self.restart_interval as u64) + 7) % 8) as u8;
+        if next_marker == expected + 0xd0 {
+            // info!("RST {:x} ix={} iy={} mcu_ptr={}", expected, ix, iy, mcu_ptr);
+            Ok(())
+        } else {
+            Err(Error::from(ErrorKind::BadRestartMarker {
+                expected: expected + 0xd0,
+                found: next_marker,
+            })
+            .at_mcu(ix, iy))
+        }
+    }
+    fn is_restart_point(&self, mcu_ptr: u64) -> bool {
+        mcu_ptr > 0 && self.restart_interval != 0 && mcu_ptr.is_multiple_of(self.restart_interval as u64)
+    }
+    // a marker the entropy decoder read ahead is handed back to next_marker
+    fn unread(&mut self, marker: Option<u8>) {
+        if marker.is_some() {
+            self.unread_marker = marker;
+        }
+    }
+    fn start_scan(&mut self, scan_x: u64, scan_y: u64) {
+        self.last_scan = Some((scan_x, scan_y));
+        self.mcus_total += scan_x * scan_y;
+    }
+    // skips entropy coded data up to the next marker, None at the end of the input
+    fn find_marker(&mut self) -> Result<Option<u8>> {
+        let mut previous = 0;
+        while let Some(byte) = self.read_data_byte()? {
+            if previous == 0xff && byte != 0x00 && byte != 0xff {
+                return Ok(Some(byte));
+            }
+            previous = byte;
+        }
+        Ok(None)
+    }
+    // a byte of entropy coded data, None at the end of the input
+    fn read_data_byte(&mut self) -> Result<Option<u8>> {
+        match read_u8(&mut self.reader) {
+            Ok(byte) => Ok(Some(byte)),
+            Err(e) if matches!(e.kind(), ErrorKind::Truncated) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    // in lenient mode, skips to the restart marker after an error in the MCU at mcu_ptr and returns
+    // the MCU that follows it, or None when the scan ends first. marker is one the entropy decoder
+    // already read. The restart marker is left unread for read_restart.
+    fn resync(&mut self, error: Error, mcu_ptr: u64, mcus: u64, marker: Option<u8>) -> Result<Option<u64>> {
+        if !self.lenient || self.restart_interval == 0 {
+            return Err(error);
+        }
+        let interval = self.restart_interval as u64;
+        // the interval that ends with the first marker found, RSTn ends the intervals numbered n modulo 8
+        let (mut failed, mut marker) = match *error.kind() {
+            ErrorKind::BadRestartMarker { found, .. } => (mcu_ptr / interval - 1, Some(found)),
+            _ => (mcu_ptr / interval, marker),
+        };
+        loop {
+            let m = match marker.take() {
+                Some(m) => m,
+                None => match self.find_marker()? {
+                    Some(m) => m,
+                    None => return Err(error),
+                },
+            };
+            if m < 0xc0 {
+                // not a valid marker, part of the corrupt data
+                continue;
+            }
+            if !(0xd0..=0xd7).contains(&m) {
+                warn!("{}, skipped the rest of the scan", error);
+                self.mcus_lost += mcus - mcu_ptr;
+                self.errors.push(error.at_offset(self.reader.position));
+                self.unread_marker = Some(m);
+                return Ok(None);
+            }
+            let ended = failed + (m as u64 + 8 - 0xd0 - failed % 8) % 8;
+            let next = (ended + 1) * interval;
+            if next < mcus {
+                warn!("{}, resuming at MCU {}", error, next);
+                self.mcus_lost += next - mcu_ptr;
+                self.errors.push(error.at_offset(self.reader.position));
+                self.unread_marker = Some(m);
+                return Ok(Some(next));
+            }
+            // a restart marker past the end of the scan can only come from corrupt data
+            failed = ended + 1;
+        }
     }
     fn parse_sos(&mut self) -> Result<()> {
         let content = self.read_marker_content()?;
@@ -326,29 +1132,32 @@ impl<T: Read> Decoder<T> {
         let ns = read_u8(&mut cursor)?;
         info!("ns(number of component)={}", ns);
         let mut components: Vec<Component> = Vec::new();
+        let mut frame_indices: Vec<usize> = Vec::new();
         for _i in 0..ns {
             let csj = read_u8(&mut cursor)?;
             let tj = read_u8(&mut cursor)?;
             let tdj = tj >> 4;
             let taj = tj & 0xf;
             info!("csj(scan component selector)={} tdj(dc entropy coding selector)={} taj(ac entropy coding selector)={}", csj, tdj, taj);
-            let scan_c = self
+            let frame_index = self
                 .scan_components
                 .iter()
-                .find(|&sc| sc.id == csj)
-                .ok_or(format_err!("cannot found from csj"))?;
+                .position(|sc| sc.id == csj)
+                .ok_or(ErrorKind::UnknownComponent(csj))?;
+            let scan_c = &self.scan_components[frame_index];
             components.push(Component {
                 hi: scan_c.hi,
                 vi: scan_c.vi,
                 qt_id: scan_c.qt_id,
-                tdj: tdj,
-                taj: taj,
+                tdj,
+                taj,
                 prev_dc: 0,
                 plane: Vec::new(),
                 stride: 0,
-            })
+                qt: None,
+            });
+            frame_indices.push(frame_index);
         }
-        self.components = components;
         let ss = read_u8(&mut cursor)?;
         let se = read_u8(&mut cursor)?;
         let a = read_u8(&mut cursor)?;
@@ -359,140 +1168,862 @@ impl<T: Read> Decoder<T> {
             ss, se
         );
         info!("ah(Successive approximation bit position high)={} al(Successive approximation bit position low or point transform)={}", ah, al);
-        let max_hi = self.components.iter().fold(0, |acc, v| u8::max(acc, v.hi));
-        let max_vi = self.components.iter().fold(0, |acc, v| u8::max(acc, v.vi));
-        let mcu_x = ceildiv(self.width as u64, (max_hi as u64) * 8);
-        let mcu_y = ceildiv(self.height as u64, (max_vi as u64) * 8);
-        //info!("width={} height={} mcu_x={} mcu_y={}", self.width, self.height, mcu_x, mcu_y);
-        for i in 0..self.components.len() {
-            self.components[i].stride = mcu_x as i32 * 8 * (self.components[i].hi as i32);
-            let height = mcu_y as i32 * 8 * (self.components[i].vi as i32);
-            //info!("i={} stride={} height={}", i, self.components[i].stride, height);
-            self.components[i].plane = vec![0; (height * self.components[i].stride) as usize];
-        }
-        let mut decoder = HaffDecoder::new();
-        let mut mcu_ptr: u64 = 0;
-        for iy in 0..mcu_y {
-            for ix in 0..mcu_x {
-                //parseMCU
-                //check RST
-                if mcu_ptr > 0 && self.restart_interval != 0 && mcu_ptr % (self.restart_interval as u64) == 0 {
-                    let next_marker = self.next_marker()?;
-                    let expected = ((mcu_ptr / (self.restart_interval as u64) + 7) % 8) as u8;
-                    if next_marker == expected + 0xd0 {
-                        // info!("RST {:x} ix={} iy={} mcu_ptr={}", expected, ix, iy, mcu_ptr);
-                        decoder.reset();
-                        for i in 0..self.components.len() {
-                            self.components[i].prev_dc = 0;
-                        }
-                    } else if next_marker >= 0xd0 && next_marker <= 0xd7 {
-                        return Err(format_err!(
-                            "expect RST {:x} found RST {:x} at ix={} iy={} mcu_ptr={}",
-                            expected,
-                            next_marker - 0xd0,
-                            ix, iy, mcu_ptr
-                        ));
-                    } else {
-                        return Err(format_err!(
-                            "seek RST marker found marker {:x} at ix={} iy={} mcu_ptr={}",
-                            next_marker,
-                            ix, iy, mcu_ptr
-                        ));
+        let kind = if self.progressive {
+            if (ss == 0 && se != 0) || (ss != 0 && (components.len() != 1 || se < ss || se > 63)) {
+                return Err(ErrorKind::InvalidSegment(format!("invalid spectral selection ss={} se={}", ss, se)).into());
+            }
+            // later DQT segments do not change the table used for a component already in a scan
+            for &frame_index in frame_indices.iter() {
+                if self.components[frame_index].qt.is_none() {
+                    let table = self.quantization_table(self.components[frame_index].qt_id)?;
+                    self.components[frame_index].qt = Some(table);
+                }
+            }
+            ScanKind::Progressive(ss, se, ah, al)
+        } else if self.lossless {
+            if !(1..=7).contains(&ss) {
+                return Err(ErrorKind::InvalidSegment(format!("invalid predictor {}", ss)).into());
+            }
+            if al >= self.precision {
+                return Err(ErrorKind::InvalidSegment(format!("invalid point transform {}", al)).into());
+            }
+            ScanKind::Lossless(ss, al)
+        } else {
+            ScanKind::Sequential
+        };
+        if self.banded && self.scans_complete > 0 {
+            return Err(ErrorKind::InvalidSegment("another scan after the scan of every component".to_string()).into());
+        }
+        if self.banded && components.len() != self.components.len() {
+            // the other components come in later scans, so the planes have to hold the whole frame
+            info!("scan of {} components, decoding the whole frame before the first row", components.len());
+            self.banded = false;
+            self.band_context = 0;
+            let (_, mcu_y) = self.mcu_size();
+            let unit = self.block_size() as u64;
+            let neutral = 1 << (self.precision - 1);
+            for c in self.components.iter_mut() {
+                c.plane = vec![neutral; (c.stride as u64 * mcu_y * unit * c.vi as u64) as usize];
+            }
+        }
+        let (scan_x, scan_y) = self.scan_size(&components);
+        self.start_scan(scan_x, scan_y);
+        if kind == ScanKind::Sequential
+            && self.threads > 1
+            && self.restart_interval != 0
+            && !self.push
+            && !self.banded
+            && !cfg!(target_arch = "wasm32")
+        {
+            self.parse_intervals_parallel(&frame_indices, &components, scan_x, scan_y)?;
+            self.scans_complete += 1;
+            return Ok(());
+        }
+        self.scan = Some(Scan {
+            kind,
+            decoder: self.new_entropy_decoder(),
+            components,
+            frame_indices,
+            scan_x,
+            scan_y,
+            mcu_ptr: 0,
+            first_line: 0,
+        });
+        self.decode_scan()
+    }
+    // decodes the MCUs left of the scan in progress. A push decode keeps a checkpoint at the start
+    // of every MCU row to return to when it runs out of data
+    fn decode_scan(&mut self) -> Result<()> {
+        let mut scan = match self.scan.take() {
+            Some(scan) => scan,
+            None => return Ok(()),
+        };
+        let mcus = scan.scan_x * scan.scan_y;
+        while scan.mcu_ptr < mcus {
+            let (ix, iy) = (scan.mcu_ptr % scan.scan_x, scan.mcu_ptr / scan.scan_x);
+            // the scan of a single component frame has a row of blocks per MCU row
+            let blocks_v = if scan.components.len() == 1 { scan.components[0].vi as u64 } else { 1 };
+            if self.banded && iy / blocks_v > self.band_row + self.band_context {
+                self.band_full = true;
+                self.scan = Some(scan);
+                return Ok(());
+            }
+            if self.push && ix == 0 {
+                self.checkpoint(Some(&scan));
+            }
+            let mut result = Ok(());
+            if self.is_restart_point(scan.mcu_ptr) {
+                self.unread(scan.decoder.take_marker());
+                result = self.read_restart(scan.mcu_ptr, ix, iy);
+                scan.decoder.reset();
+                for c in scan.components.iter_mut() {
+                    c.prev_dc = 0;
+                }
+                scan.first_line = iy;
+            }
+            if result.is_ok() {
+                let decoder = &mut scan.decoder;
+                let (components, frame_indices) = (&mut scan.components, &scan.frame_indices);
+                result = match scan.kind {
+                    ScanKind::Sequential => self.parse_mcu(decoder, components, frame_indices, ix, iy),
+                    ScanKind::Progressive(ss, se, ah, al) => {
+                        self.parse_progressive_mcu(decoder, components, frame_indices, ix, iy, (ss, se, ah, al))
+                    }
+                    ScanKind::Lossless(predictor, pt) => self.parse_lossless_mcu(
+                        decoder,
+                        components,
+                        frame_indices,
+                        ix,
+                        iy,
+                        scan.first_line,
+                        (predictor, pt),
+                    ),
+                };
+            }
+            match result {
+                Ok(()) => scan.mcu_ptr += 1,
+                // the checkpoint has the state to continue from
+                Err(e) if self.push && e.is_would_block() => return Err(e),
+                Err(e) => match self.resync(e.at_mcu(ix, iy), scan.mcu_ptr, mcus, scan.decoder.take_marker())? {
+                    Some(next) => scan.mcu_ptr = next,
+                    None => break,
+                },
+            }
+        }
+        self.unread(scan.decoder.take_marker());
+        self.scans_complete += 1;
+        Ok(())
+    }
+    fn parse_mcu(
+        &mut self,
+        decoder: &mut EntropyDecoder,
+        components: &mut [Component],
+        frame_indices: &[usize],
+        ix: u64,
+        iy: u64,
+    ) -> Result<()> {
+        let non_interleaved = components.len() == 1;
+        for i in 0..components.len() {
+            let (blocks_h, blocks_v) = if non_interleaved {
+                (1, 1)
+            } else {
+                (components[i].hi as u64, components[i].vi as u64)
+            };
+            for iv in 0..blocks_v {
+                for ih in 0..blocks_h {
+                    let (blocks, reader) = self.blocks_and_reader();
+                    let c = &mut components[i];
+                    let mut coeffs = blocks.parse_coeffs(reader, decoder, i, c.tdj, c.taj)?;
+                    coeffs[0] += c.prev_dc;
+                    c.prev_dc = coeffs[0];
+                    let (bx, by) = (ix * blocks_h + ih, iy * blocks_v + iv);
+                    if blocks.in_region(frame_indices[i], bx, by) {
+                        let block = blocks.render_block(c.qt_id, coeffs)?;
+                        self.store_block(frame_indices[i], bx, by, &block);
                     }
                 }
-                mcu_ptr += 1;
-                for i in 0..self.components.len() {
-                    for iv in 0..self.components[i].vi {
-                        for ih in 0..self.components[i].hi {
-                            //info!("MCU ix={} iy={} ih={} iv={}", ix, iy, ih, iv);
-                            let (dc, parsed) = match self.parse_block(
-                                &mut decoder,
-                                self.components[i].qt_id,
-                                self.components[i].taj,
-                                self.components[i].tdj,
-                                self.components[i].prev_dc,
-                            ) {
-                                Ok(v) => v,
-                                Err(e) => {
-                                    warn!("parse block error at i={} ix={} iy={} ih={} iv={} mcu_ptr={} ",i, ix, iy, ih, iv, mcu_ptr);
-                                    Err(e)?
-                                }
+            }
+        }
+        Ok(())
+    }
+    // selection is (ss, se, ah, al) of the scan
+    fn parse_progressive_mcu(
+        &mut self,
+        decoder: &mut EntropyDecoder,
+        components: &mut [Component],
+        frame_indices: &[usize],
+        ix: u64,
+        iy: u64,
+        selection: (u8, u8, u8, u8),
+    ) -> Result<()> {
+        let (ss, se, ah, al) = selection;
+        let (mcu_x, _) = self.mcu_size();
+        let non_interleaved = components.len() == 1;
+        for (i, c) in components.iter_mut().enumerate() {
+            let (blocks_h, blocks_v) = if non_interleaved { (1, 1) } else { (c.hi as u64, c.vi as u64) };
+            let stride = mcu_x * c.hi as u64;
+            for iv in 0..blocks_v {
+                for ih in 0..blocks_h {
+                    let block_index = (iy * blocks_v + iv) * stride + ix * blocks_h + ih;
+                    self.parse_progressive_block(
+                        decoder,
+                        i,
+                        frame_indices[i],
+                        block_index as usize,
+                        c,
+                        ss,
+                        se,
+                        ah,
+                        al,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+    // transform is (predictor, pt) of the scan, the first line of the scan and of each restart
+    // interval is predicted from the left only
+    #[allow(clippy::too_many_arguments)]
+    fn parse_lossless_mcu(
+        &mut self,
+        decoder: &mut EntropyDecoder,
+        components: &[Component],
+        frame_indices: &[usize],
+        ix: u64,
+        iy: u64,
+        first_line: u64,
+        transform: (u8, u8),
+    ) -> Result<()> {
+        let (predictor, pt) = transform;
+        let initial = 1 << (self.precision - pt - 1);
+        let non_interleaved = components.len() == 1;
+        for (i, c) in components.iter().enumerate() {
+            let (units_h, units_v) = if non_interleaved { (1, 1) } else { (c.hi as u64, c.vi as u64) };
+            for iv in 0..units_v {
+                for ih in 0..units_h {
+                    let x = (ix * units_h + ih) as usize;
+                    let y = (iy * units_v + iv) as usize;
+                    let first = (first_line * units_v) as usize;
+                    let target = &self.components[frame_indices[i]];
+                    let stride = target.stride as usize;
+                    let predict = |x: usize, y: usize| {
+                        predict_lossless(&target.plane, stride, x, y, y == first, predictor, initial, pt)
+                    };
+                    let prediction = predict(x, y);
+                    let diff = match decoder {
+                        EntropyDecoder::Haff(decoder) => {
+                            let dc_haff = self
+                                .hafftables
+                                .iter()
+                                .find(|&ht| c.tdj == ht.id && ht.tc == 0)
+                                .ok_or(ErrorKind::MissingTable { class: TableClass::HuffmanDc, id: c.tdj })?;
+                            decoder.parse_lossless_diff(&mut self.reader, dc_haff)?
+                        }
+                        EntropyDecoder::Arith(decoder) => {
+                            // the differences of the decoded neighbours, taken again from their samples
+                            let coded = |x: usize, y: usize| {
+                                let sample = (target.plane[y * stride + x] >> pt) as i32;
+                                (sample - predict(x, y) + 32767).rem_euclid(65536) - 32767
                             };
-                            let c = &mut self.components[i];
-                            c.prev_dc = dc;
-                            let offset_x = ix as i32 * 8 * (c.hi as i32) + (ih as i32) * 8;
-                            let offset_y = iy as i32 * 8 * (c.vi as i32) + (iv as i32) * 8;
-                            for iy in 0..8 {
-                                for ix in 0..8 {
-                                    let offset =
-                                        (offset_x + ix + (offset_y + iy) * c.stride) as usize;
-                                    c.plane[offset] = parsed[iy as usize][ix as usize];
-                                }
-                            }
+                            let da = if x > 0 { coded(x - 1, y) } else { 0 };
+                            let db = if y > first { coded(x, y - 1) } else { 0 };
+                            decoder.parse_lossless_diff(&mut self.reader, c.tdj, da, db)?
                         }
-                    }
+                    };
+                    let target = &mut self.components[frame_indices[i]];
+                    target.plane[y * stride + x] = (((prediction + diff) & 0xffff) << pt) as u16;
                 }
             }
         }
         Ok(())
     }
-    pub fn get_rgb_vec(&self, alpha: bool) -> Vec<u8> {
-        let max_hi = self.components.iter().fold(0, |acc, v| u8::max(acc, v.hi));
-        let max_vi = self.components.iter().fold(0, |acc, v| u8::max(acc, v.vi));
-        let mut vec = Vec::with_capacity(self.height as usize * self.width as usize * 3);
-        for iy in 0..self.height {
-            for ix in 0..self.width {
-                let mut v = [0.; 3];
-                for k in 0..3 {
-                    let c = &self.components[k];
-                    let offset_x = ix as i32 * c.hi as i32 / max_hi as i32;
-                    let offset_y = iy as i32 * c.vi as i32 / max_vi as i32;
-                    v[k] = c.plane[(offset_y * c.stride + offset_x) as usize] as f64;
+    fn render_progressive(&mut self) -> Result<()> {
+        for (frame_index, coeffs) in self.coeffs.iter().enumerate() {
+            let table = match self.components[frame_index].qt {
+                Some(table) => table,
+                None => self.quantization_table(self.components[frame_index].qt_id)?,
+            };
+            let stride = self.components[frame_index].stride as usize;
+            let mut plane = vec![0; self.components[frame_index].plane.len()];
+            let block_size = self.block_size();
+            let blocks_x = stride / block_size;
+            for (block_index, block) in coeffs.iter().enumerate() {
+                let (bx, by) = (block_index % blocks_x, block_index / blocks_x);
+                if !self.in_region(frame_index, bx as u64, by as u64) {
+                    continue;
                 }
-                let r = tr255(v[0] + 1.402 * (v[2] - 128.));
-                let g = tr255(v[0] - 0.34414 * (v[1] - 128.) - 0.71414 * (v[2] - 128.));
-                let b = tr255(v[0] + 1.772 * (v[1] - 128.));
-                vec.push(r as u8);
-                vec.push(g as u8);
-                vec.push(b as u8);
-                if alpha {
-                    vec.push(255)
+                let mut dequantized = *block;
+                dequantize(&table, &mut dequantized);
+                let idcted = self.idct(&dequantized);
+                let offset_x = bx * block_size;
+                let offset_y = by * block_size;
+                for (iy, row) in idcted.iter().take(block_size).enumerate() {
+                    let offset = (offset_y + iy) * stride + offset_x;
+                    plane[offset..offset + block_size].copy_from_slice(&row[..block_size]);
                 }
             }
+            self.components[frame_index].plane = plane;
+        }
+        Ok(())
+    }
+    // calls f with the samples of every component at each pixel of the rows, upsampled to full resolution
+    fn for_each_pixel<F: FnMut(&[f64])>(&self, rows: Range<u16>, mut f: F) {
+        let max_hi = self.components.iter().map(|v| v.hi).max().unwrap_or(1);
+        let max_vi = self.components.iter().map(|v| v.vi).max().unwrap_or(1);
+        let mut v = [0.; 4];
+        // frames that failed before their first scan come out black
+        let v = &mut v[..self.components.len().max(1)];
+        let (x0, y0, width, _) = self.crop_rect();
+        let block_size = self.block_size() as i32;
+        let (scaled_width, scaled_height) = self.scaled_size();
+        let filters: Vec<Filter> = self.components.iter().map(|c| self.filter(c.hi, c.vi)).collect();
+        for iy in rows {
+            let y = y0 as i32 + iy as i32;
+            for ix in 0..width {
+                let x = x0 as i32 + ix as i32;
+                for ((vk, c), &filter) in v.iter_mut().zip(self.components.iter()).zip(filters.iter()) {
+                    let first = self.band_row as i32 * c.vi as i32 * block_size - self.band_context as i32;
+                    if filter != Filter::Replicate {
+                        let size = (
+                            ceildiv(scaled_width as u64 * c.hi as u64, max_hi as u64) as i32,
+                            ceildiv(scaled_height as u64 * c.vi as u64, max_vi as u64) as i32,
+                        );
+                        *vk = upsample(c, filter, x, y, size, first) as f64;
+                        continue;
+                    }
+                    // the sample covering the pixel, which also works for ratios such as 3:2
+                    let offset_x = x * c.hi as i32 / max_hi as i32;
+                    let offset_y = y * c.vi as i32 / max_vi as i32 - first;
+                    *vk = c.plane[(offset_y * c.stride + offset_x) as usize] as f64;
+                }
+                f(v);
+            }
+        }
+    }
+    pub fn get_color_space(&self) -> ColorSpace {
+        match self.components.len() {
+            // like libjpeg, a JFIF segment means YCbCr, otherwise an Adobe segment tells and without
+            // either component ids 'R', 'G' and 'B' mean RGB
+            3 => {
+                let ids: Vec<u8> = self.scan_components.iter().map(|sc| sc.id).collect();
+                match (self.jfif, self.adobe_transform) {
+                    (None, Some(0)) => ColorSpace::Rgb,
+                    (None, None) if ids == b"RGB" => ColorSpace::Rgb,
+                    _ => ColorSpace::YCbCr,
+                }
+            }
+            // four component frames are CMYK unless an Adobe segment says they are YCCK
+            4 => match self.adobe_transform {
+                None | Some(0) => ColorSpace::Cmyk,
+                Some(_) => ColorSpace::Ycck,
+            },
+            _ => ColorSpace::Gray,
+        }
+    }
+    // RGB of the first three samples, not clamped yet
+    fn ycbcr_to_rgb(&self, matrix: ColorMatrix, v: &[f64]) -> [f64; 3] {
+        let half = (1 << (self.precision - 1)) as f64;
+        let (y, cb, cr) = match matrix {
+            ColorMatrix::Bt601 => (v[0], v[1] - half, v[2] - half),
+            _ => {
+                // limited range samples are stretched to full range first
+                let unit = (1 << self.precision) as f64 / 256.;
+                ((v[0] - 16. * unit) * 255. / 219., (v[1] - half) * 255. / 224., (v[2] - half) * 255. / 224.)
+            }
+        };
+        let (red_cr, green_cb, green_cr, blue_cb) = matrix.coefficients();
+        [y + red_cr * cr, y - green_cb * cb - green_cr * cr, y + blue_cb * cb]
+    }
+    fn to_rgb(&self, color_space: ColorSpace, v: &[f64]) -> [i32; 3] {
+        let max = (1 << self.precision) - 1;
+        match color_space {
+            ColorSpace::Gray => {
+                let y = clamp_sample(v[0], max);
+                [y, y, y]
+            }
+            ColorSpace::YCbCr => {
+                let [r, g, b] = self.ycbcr_to_rgb(self.color_matrix, v);
+                [clamp_sample(r, max), clamp_sample(g, max), clamp_sample(b, max)]
+            }
+            ColorSpace::Rgb => [clamp_sample(v[0], max), clamp_sample(v[1], max), clamp_sample(v[2], max)],
+            ColorSpace::Cmyk | ColorSpace::Ycck => {
+                let [c, m, y, k] = self.to_cmyk(color_space, v);
+                let k = (max - k) as f64 / max as f64;
+                [
+                    clamp_sample((max - c) as f64 * k + 0.5, max),
+                    clamp_sample((max - m) as f64 * k + 0.5, max),
+                    clamp_sample((max - y) as f64 * k + 0.5, max),
+                ]
+            }
+        }
+    }
+    // ink amounts where 0 means no ink
+    fn to_cmyk(&self, color_space: ColorSpace, v: &[f64]) -> [i32; 4] {
+        let max = (1 << self.precision) - 1;
+        match color_space {
+            // Adobe (Photoshop) writes inverted CMYK
+            ColorSpace::Cmyk if self.adobe_transform.is_some() => [
+                max - clamp_sample(v[0], max),
+                max - clamp_sample(v[1], max),
+                max - clamp_sample(v[2], max),
+                max - clamp_sample(v[3], max),
+            ],
+            ColorSpace::Cmyk => [
+                clamp_sample(v[0], max),
+                clamp_sample(v[1], max),
+                clamp_sample(v[2], max),
+                clamp_sample(v[3], max),
+            ],
+            // YCCK comes from inverted CMYK whose CMY part was inverted once more before YCbCr conversion
+            ColorSpace::Ycck => {
+                let [c, m, y] = self.ycbcr_to_rgb(ColorMatrix::Bt601, v);
+                [clamp_sample(c, max), clamp_sample(m, max), clamp_sample(y, max), max - clamp_sample(v[3], max)]
+            }
+            _ => {
+                let [r, g, b] = self.to_rgb(color_space, v);
+                let k = max - i32::max(r, i32::max(g, b));
+                if k == max {
+                    return [0, 0, 0, k];
+                }
+                let scale = max as f64 / (max - k) as f64;
+                let ink = |v: i32| clamp_sample((max - k - v) as f64 * scale + 0.5, max);
+                [ink(r), ink(g), ink(b), k]
+            }
+        }
+    }
+    fn for_each_rgb<F: FnMut(i32, i32, i32)>(&self, rows: Range<u16>, mut f: F) {
+        let color_space = self.get_color_space();
+        self.for_each_pixel(rows, |v| {
+            let [r, g, b] = self.to_rgb(color_space, v);
+            f(r, g, b);
+        });
+    }
+    fn for_each_cmyk<F: FnMut(i32, i32, i32, i32)>(&self, rows: Range<u16>, mut f: F) {
+        let color_space = self.get_color_space();
+        self.for_each_pixel(rows, |v| {
+            let [c, m, y, k] = self.to_cmyk(color_space, v);
+            f(c, m, y, k);
+        });
+    }
+    fn for_each_gray<F: FnMut(i32)>(&self, rows: Range<u16>, mut f: F) {
+        let max = (1 << self.precision) - 1;
+        match self.get_color_space() {
+            // luma of YCbCr frames is used as is, or stretched to full range
+            ColorSpace::YCbCr if self.color_matrix != ColorMatrix::Bt601 => {
+                let unit = (1 << self.precision) as f64 / 256.;
+                self.for_each_pixel(rows, |v| f(clamp_sample((v[0] - 16. * unit) * 255. / 219., max)));
+            }
+            ColorSpace::Gray | ColorSpace::YCbCr => self.for_each_pixel(rows, |v| f(clamp_sample(v[0], max))),
+            _ => self.for_each_rgb(rows, |r, g, b| f((r * 299 + g * 587 + b * 114 + 500) / 1000)),
+        }
+    }
+    fn all_rows(&self) -> Range<u16> {
+        0..self.output_size().1
+    }
+    fn row_pixels(&self, rows: &Range<u16>) -> usize {
+        self.output_size().0 as usize * rows.len()
+    }
+    pub fn get_rgb_vec(&self, alpha: bool) -> Vec<u8> {
+        self.rgb_rows(self.all_rows(), false, alpha)
+    }
+    // blue comes first if bgr is set, alpha is always opaque
+    pub(crate) fn rgb_rows(&self, rows: Range<u16>, bgr: bool, alpha: bool) -> Vec<u8> {
+        let precision = self.precision;
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows) * if alpha { 4 } else { 3 });
+        self.for_each_rgb(rows, |r, g, b| {
+            let (first, last) = if bgr { (b, r) } else { (r, b) };
+            vec.push(scale_to_u8(first, precision));
+            vec.push(scale_to_u8(g, precision));
+            vec.push(scale_to_u8(last, precision));
+            if alpha {
+                vec.push(255)
+            }
+        });
+        vec
+    }
+    // 5 bits of red, 6 of green and 5 of blue from the high bit down, in a little endian u16 per pixel
+    pub(crate) fn rgb565_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let precision = self.precision;
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows) * 2);
+        self.for_each_rgb(rows, |r, g, b| {
+            let [r, g, b] = [r, g, b].map(|v| scale_to_u8(v, precision) as u16);
+            vec.extend_from_slice(&((r >> 3) << 11 | (g >> 2) << 5 | b >> 3).to_le_bytes());
+        });
+        vec
+    }
+    // the Y, Cb and Cr planes of the rows one after the other. YCbCr frames give their samples as
+    // they are, the others are converted from RGB as in JFIF
+    pub(crate) fn ycbcr_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let precision = self.precision;
+        let pixels = self.row_pixels(&rows);
+        let mut vec = vec![0; pixels * 3];
+        let mut i = 0;
+        let mut put = |y: i32, cb: i32, cr: i32| {
+            vec[i] = scale_to_u8(y, precision);
+            vec[pixels + i] = scale_to_u8(cb, precision);
+            vec[pixels * 2 + i] = scale_to_u8(cr, precision);
+            i += 1;
+        };
+        let max = (1 << precision) - 1;
+        let half = (1 << (precision - 1)) as f64;
+        match self.get_color_space() {
+            ColorSpace::YCbCr => self.for_each_pixel(rows, |v| {
+                put(clamp_sample(v[0], max), clamp_sample(v[1], max), clamp_sample(v[2], max))
+            }),
+            _ => self.for_each_rgb(rows, |r, g, b| {
+                let (r, g, b) = (r as f64, g as f64, b as f64);
+                put(
+                    clamp_sample(0.299 * r + 0.587 * g + 0.114 * b + 0.5, max),
+                    clamp_sample(-0.168736 * r - 0.331264 * g + 0.5 * b + half + 0.5, max),
+                    clamp_sample(0.5 * r - 0.418688 * g - 0.081312 * b + half + 0.5, max),
+                )
+            }),
         }
         vec
     }
+    pub fn get_rgb16_vec(&self) -> Vec<u16> {
+        let mut vec = Vec::with_capacity(self.output_pixels() * 3);
+        self.for_each_rgb(self.all_rows(), |r, g, b| {
+            vec.push(r as u16);
+            vec.push(g as u16);
+            vec.push(b as u16);
+        });
+        vec
+    }
+    // samples with the precision of the frame in a little endian u16 each
+    pub(crate) fn rgb16_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows) * 6);
+        self.for_each_rgb(rows, |r, g, b| {
+            for v in [r, g, b] {
+                vec.extend_from_slice(&(v as u16).to_le_bytes());
+            }
+        });
+        vec
+    }
+    pub fn get_cmyk_vec(&self) -> Vec<u8> {
+        self.cmyk_rows(self.all_rows())
+    }
+    pub(crate) fn cmyk_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let precision = self.precision;
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows) * 4);
+        self.for_each_cmyk(rows, |c, m, y, k| {
+            vec.push(scale_to_u8(c, precision));
+            vec.push(scale_to_u8(m, precision));
+            vec.push(scale_to_u8(y, precision));
+            vec.push(scale_to_u8(k, precision));
+        });
+        vec
+    }
+    pub fn get_gray_vec(&self) -> Vec<u8> {
+        self.gray_rows(self.all_rows())
+    }
+    pub(crate) fn gray_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let precision = self.precision;
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows));
+        self.for_each_gray(rows, |v| vec.push(scale_to_u8(v, precision)));
+        vec
+    }
+    pub(crate) fn gray16_rows(&self, rows: Range<u16>) -> Vec<u8> {
+        let mut vec = Vec::with_capacity(self.row_pixels(&rows) * 2);
+        self.for_each_gray(rows, |v| vec.extend_from_slice(&(v as u16).to_le_bytes()));
+        vec
+    }
+    pub fn get_gray16_vec(&self) -> Vec<u16> {
+        let mut vec = Vec::with_capacity(self.output_pixels());
+        self.for_each_gray(self.all_rows(), |v| vec.push(v as u16));
+        vec
+    }
+    pub fn outputpgm<T2: Write>(&self, w: &mut T2) -> Result<()> {
+        writeln!(w, "P5")?;
+        let (width, height) = self.output_size();
+        writeln!(w, "{} {}", width, height)?;
+        if self.precision > 8 {
+            writeln!(w, "{}", (1 << self.precision) - 1)?;
+            for v in self.get_gray16_vec() {
+                w.write_all(&v.to_be_bytes())?;
+            }
+        } else {
+            writeln!(w, "255")?;
+            w.write_all(&self.get_gray_vec())?;
+        }
+        Ok(())
+    }
+    /// CMYK is written as a PAM with 8-bit samples
+    pub fn outputpam<T2: Write>(&self, w: &mut T2) -> Result<()> {
+        writeln!(w, "P7")?;
+        let (width, height) = self.output_size();
+        writeln!(w, "WIDTH {}", width)?;
+        writeln!(w, "HEIGHT {}", height)?;
+        writeln!(w, "DEPTH 4")?;
+        writeln!(w, "MAXVAL 255")?;
+        writeln!(w, "TUPLTYPE CMYK")?;
+        writeln!(w, "ENDHDR")?;
+        w.write_all(&self.get_cmyk_vec())?;
+        Ok(())
+    }
+    /// the planes of the components as decoded, before upsampling and color conversion. Their size
+    /// is that of the components of the scaled image, or the size of the crop rectangle at the rate
+    /// of the component from the sample of its first pixel, so a crop at an offset that is not a
+    /// multiple of the subsampling shifts the chroma. next_rows only keeps a band of the planes, so
+    /// they are complete after decode
+    pub fn get_planes(&self) -> Vec<Plane<'_>> {
+        let (max_hi, max_vi) = self.max_sampling();
+        let (x, y, width, height) = self.crop_rect();
+        // first and end sample of the pixels from start to start + len
+        let span = |start: u16, len: u16, factor: u8, max: u8| {
+            let first = start as usize * factor as usize / max as usize;
+            (first, first + ceildiv(len as u64 * factor as u64, max as u64) as usize)
+        };
+        self.components
+            .iter()
+            .map(|c| {
+                let (x0, x1) = span(x, width, c.hi, max_hi);
+                let (y0, y1) = span(y, height, c.vi, max_vi);
+                let stride = c.stride as usize;
+                Plane {
+                    samples: &c.plane[(y0 * stride + x0).min(c.plane.len())..],
+                    stride,
+                    width: x1 - x0,
+                    height: y1 - y0,
+                }
+            })
+            .collect()
+    }
+    /// YCbCr and grayscale images are written as a YUV4MPEG2 stream of one frame holding their
+    /// planes, which needs the chroma at 1/1, 1/2 or 1/4 the horizontal and 1/1 or 1/2 the vertical
+    /// luma rate. Samples of more than 8 bits are written as 16-bit ones
+    pub fn outputy4m<T2: Write>(&self, w: &mut T2) -> Result<()> {
+        let deep = self.precision > 8;
+        let sampling: Vec<(u8, u8)> = self.components.iter().map(|c| (c.hi, c.vi)).collect();
+        let chroma = match self.get_color_space() {
+            ColorSpace::Gray => Some(if deep { "mono16" } else { "mono" }),
+            ColorSpace::YCbCr if sampling[1] == sampling[2] => {
+                let ((hy, vy), (hc, vc)) = (sampling[0], sampling[1]);
+                let ratio = if hy % hc == 0 && vy % vc == 0 { (hy / hc, vy / vc) } else { (0, 0) };
+                match (ratio, deep) {
+                    ((1, 1), false) => Some("444"),
+                    ((2, 1), false) => Some("422"),
+                    ((2, 2), false) => Some("420jpeg"),
+                    ((4, 1), false) => Some("411"),
+                    ((1, 1), true) => Some("444p16"),
+                    ((2, 1), true) => Some("422p16"),
+                    ((2, 2), true) => Some("420p16"),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let chroma = match chroma {
+            Some(chroma) => chroma,
+            None => {
+                let color_space = self.get_color_space();
+                let message = format!("no y4m format for {:?} with sampling factors {:?}", color_space, sampling);
+                return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, message).into());
+            }
+        };
+        let (width, height) = self.output_size();
+        writeln!(w, "YUV4MPEG2 W{} H{} F25:1 Ip C{}", width, height, chroma)?;
+        writeln!(w, "FRAME")?;
+        for plane in self.get_planes() {
+            for y in 0..plane.height {
+                let row = plane.row(y);
+                if deep {
+                    let bytes: Vec<u8> = row.iter().flat_map(|&v| (v << (16 - self.precision)).to_le_bytes()).collect();
+                    w.write_all(&bytes)?;
+                } else {
+                    let bytes: Vec<u8> = row.iter().map(|&v| scale_to_u8(v as i32, self.precision)).collect();
+                    w.write_all(&bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    /// grayscale images are written as PGM
     pub fn outputppm<T2: Write>(&self, w: &mut T2) -> Result<()> {
+        if self.components.len() == 1 {
+            return self.outputpgm(w);
+        }
         writeln!(w, "P6")?;
-        writeln!(w, "{} {}", self.width, self.height)?;
-        writeln!(w, "255")?;
-        w.write(&self.get_rgb_vec(false))?;
+        let (width, height) = self.output_size();
+        writeln!(w, "{} {}", width, height)?;
+        if self.precision > 8 {
+            writeln!(w, "{}", (1 << self.precision) - 1)?;
+            for v in self.get_rgb16_vec() {
+                w.write_all(&v.to_be_bytes())?;
+            }
+        } else {
+            writeln!(w, "255")?;
+            w.write_all(&self.get_rgb_vec(false))?;
+        }
         Ok(())
     }
+    /// errors carry the number of bytes consumed from the reader when they were detected
     pub fn decode(&mut self) -> Result<()> {
-        check_soi(&mut self.reader)?;
-        info!("SOI found");
+        let result = self.decode_markers();
+        self.end_decode(result)
+    }
+    // decodes up to the end of the next row of MCUs and returns the range of image rows that are
+    // complete, None once every row was returned. Only the rows of the last range can be read, unless
+    // the frame is progressive, lossless or has more than one scan and so is decoded as a whole
+    pub(crate) fn decode_band(&mut self) -> Result<Option<Range<u16>>> {
+        // bands outside the crop rectangle are decoded but not returned
+        while let Some(rows) = self.next_band()? {
+            let (_, y, _, height) = self.crop_rect();
+            let rows = rows.start.clamp(y, y + height) - y..rows.end.clamp(y, y + height) - y;
+            if !rows.is_empty() {
+                return Ok(Some(rows));
+            }
+        }
+        Ok(None)
+    }
+    // next_band in rows of the scaled image
+    fn next_band(&mut self) -> Result<Option<Range<u16>>> {
+        if self.rows_done {
+            return Ok(None);
+        }
+        self.by_rows = true;
+        if self.band_full {
+            self.band_full = false;
+            self.band_row += 1;
+            let neutral = 1 << (self.precision - 1);
+            let block_size = self.block_size();
+            for c in self.components.iter_mut() {
+                // the sample rows kept for the next band move to the top
+                let band = c.vi as usize * block_size * c.stride as usize;
+                let len = c.plane.len();
+                c.plane.copy_within(band.., 0);
+                c.plane[len - band..].fill(neutral);
+            }
+        }
+        if !self.decoded {
+            let result = self.decode_markers();
+            if result.is_err() || !self.band_full {
+                self.decoded = true;
+                self.end_decode(result)?;
+            }
+        }
+        let height = self.scaled_size().1 as u64;
+        let band_height = self.max_sampling().1 as u64 * self.block_size() as u64;
+        let band = (self.band_row * band_height).min(height) as u16..((self.band_row + 1) * band_height).min(height) as u16;
+        if self.band_full {
+            return Ok(Some(band));
+        }
+        if !self.banded {
+            self.rows_done = true;
+            return Ok(Some(0..height as u16));
+        }
+        // rows a scan that stopped early did not reach come in bands of neutral samples
+        self.band_full = true;
+        self.rows_done = band.end as u64 == height;
+        Ok(Some(band))
+    }
+    // in lenient mode an error that stopped decoding goes into the recovery report instead
+    fn end_decode(&mut self, result: Result<()>) -> Result<()> {
+        if let Err(e) = result {
+            let error = e.at_offset(self.reader.position);
+            if !self.lenient || self.components.is_empty() {
+                return Err(error);
+            }
+            if self.progressive && self.render_progressive().is_err() {
+                return Err(error);
+            }
+            match self.last_scan {
+                Some((scan_x, scan_y)) => {
+                    if let Some((x, y)) = error.mcu_position() {
+                        self.mcus_lost += scan_x * scan_y - (y * scan_x + x);
+                    }
+                }
+                None => {
+                    let (mcu_x, mcu_y) = self.mcu_size();
+                    self.mcus_total = mcu_x * mcu_y;
+                    self.mcus_lost = self.mcus_total;
+                }
+            }
+            warn!("stopped decoding: {}", error);
+            self.errors.push(error);
+        }
+        if !self.errors.is_empty() {
+            let mcus_recovered = self.mcus_total - self.mcus_lost;
+            warn!("recovered {} of {} MCUs", mcus_recovered, self.mcus_total);
+            self.recovery = Some(Recovery {
+                errors: std::mem::take(&mut self.errors),
+                mcus_recovered,
+                mcus_total: self.mcus_total,
+            });
+        }
+        Ok(())
+    }
+    fn decode_markers(&mut self) -> Result<()> {
+        // a push decode resumes past SOI, possibly within a scan
+        if self.reader.position == 0 {
+            check_soi(&mut self.reader)?;
+            info!("SOI found");
+        }
+        self.decode_scan()?;
         loop {
+            if self.band_full {
+                return Ok(());
+            }
+            if self.push {
+                self.checkpoint(None);
+            }
             match self.next_marker()? {
                 0xe0 => self.parse_app0()?,
+                0xee => self.parse_app14()?,
                 m @ 0xe1..=0xef => self.parse_app(m - 0xe0)?,
                 0xdb => self.parse_dqt()?,
-                0xc0 => self.parse_sof0()?,
+                m @ (0xc0..=0xc3 | 0xc9..=0xcb) => self.parse_sof(m - 0xc0)?,
+                // differential frames of the hierarchical process, the DHP and EXP segments before
+                // them are skipped so that decoding stops at the first one
+                m @ (0xc5..=0xc7 | 0xcd..=0xcf) => return Err(ErrorKind::UnsupportedFrame(m - 0xc0).into()),
+                m @ (0xde | 0xdf) => {
+                    let content = self.read_marker_content()?;
+                    info!("{} size={}", if m == 0xde { "DHP" } else { "EXP" }, content.len());
+                }
                 0xc4 => self.parse_dht()?,
+                0xcc => self.parse_dac()?,
                 0xda => self.parse_sos()?,
                 0xdd => self.parse_dri()?,
                 0xd9 => {
                     info!("reached EOI");
+                    if self.progressive {
+                        self.render_progressive()?;
+                    }
                     return Ok(());
                 }
-                m => return Err(format_err!("unknown marker {:x}", m)),
+                // left over from a scan that lost restart intervals
+                m @ 0xd0..=0xd7 if self.lenient => {
+                    let error = Error::from(ErrorKind::UnexpectedMarker(m)).at_offset(self.reader.position);
+                    warn!("{}, skipped", error);
+                    self.errors.push(error);
+                }
+                m => return Err(ErrorKind::UnknownMarker(m).into()),
             }
         }
     }
+    /// size of the decoded image, which is the frame size divided by the scale and cropped
     pub fn get_height(&self) -> u16 {
-        self.height
+        self.output_size().1
     }
     pub fn get_width(&self) -> u16 {
+        self.output_size().0
+    }
+    pub fn get_frame_height(&self) -> u16 {
+        self.height
+    }
+    pub fn get_frame_width(&self) -> u16 {
         self.width
     }
+    pub fn get_precision(&self) -> u8 {
+        self.precision
+    }
+    pub fn get_component_count(&self) -> usize {
+        self.scan_components.len()
+    }
+    /// (id, horizontal and vertical sampling factor) of every frame component
+    pub fn get_sampling_factors(&self) -> Vec<(u8, u8, u8)> {
+        self.scan_components.iter().map(|sc| (sc.id, sc.hi, sc.vi)).collect()
+    }
+    pub fn is_progressive(&self) -> bool {
+        self.progressive
+    }
+    pub fn is_lossless(&self) -> bool {
+        self.lossless
+    }
+    pub fn is_arithmetic(&self) -> bool {
+        self.arithmetic
+    }
+    pub fn get_restart_interval(&self) -> u16 {
+        self.restart_interval
+    }
+    pub fn get_jfif(&self) -> Option<Jfif> {
+        self.jfif
+    }
+    pub fn get_adobe_transform(&self) -> Option<u8> {
+        self.adobe_transform
+    }
+    /// set when a lenient decode went past errors
+    pub fn get_recovery(&self) -> Option<&Recovery> {
+        self.recovery.as_ref()
+    }
 }
@@ -0,0 +1,267 @@
+use super::{BlockDecoder, Component, Decoder, Error, ErrorKind, Result};
+use log::{info, warn};
+use std::io::{Cursor, Read};
+use std::thread;
+
+// entropy coded data of a scan up to and including the marker that ends it
+struct Segment {
+    start: usize,
+    end: usize,
+    marker: Option<u8>,
+}
+
+// blocks of a restart interval in decoding order, None for those outside the crop region, up to
+// the error that stopped it along with the MCU of the interval it happened in
+struct DecodedInterval {
+    blocks: Vec<Option<[[u16; 8]; 8]>>,
+    error: Option<(u64, Error)>,
+}
+
+// decodes the MCUs from first to first + mcus of a scan scan_x MCUs wide
+#[allow(clippy::too_many_arguments)]
+fn decode_interval(
+    blocks: &BlockDecoder,
+    data: &[u8],
+    offset: u64,
+    components: &[Component],
+    frame_indices: &[usize],
+    first: u64,
+    mcus: u64,
+    scan_x: u64,
+) -> DecodedInterval {
+    let mut reader = Cursor::new(data);
+    let mut decoder = blocks.new_entropy_decoder();
+    let mut prev_dc = vec![0; components.len()];
+    let mut decoded = Vec::new();
+    let non_interleaved = components.len() == 1;
+    for mcu in 0..mcus {
+        let (ix, iy) = ((first + mcu) % scan_x, (first + mcu) / scan_x);
+        for (i, c) in components.iter().enumerate() {
+            let (blocks_h, blocks_v) = if non_interleaved { (1, 1) } else { (c.hi as u64, c.vi as u64) };
+            for iv in 0..blocks_v {
+                for ih in 0..blocks_h {
+                    let (bx, by) = (ix * blocks_h + ih, iy * blocks_v + iv);
+                    let block = blocks.parse_coeffs(&mut reader, &mut decoder, i, c.tdj, c.taj).and_then(|mut coeffs| {
+                        coeffs[0] += prev_dc[i];
+                        prev_dc[i] = coeffs[0];
+                        if blocks.in_region(frame_indices[i], bx, by) {
+                            blocks.render_block(c.qt_id, coeffs).map(Some)
+                        } else {
+                            Ok(None)
+                        }
+                    });
+                    match block {
+                        Ok(block) => decoded.push(block),
+                        Err(e) => {
+                            let error = e.at_offset(offset + reader.position());
+                            return DecodedInterval {
+                                blocks: decoded,
+                                error: Some((mcu, error)),
+                            };
+                        }
+                    }
+                }
+            }
+        }
+    }
+    DecodedInterval {
+        blocks: decoded,
+        error: None,
+    }
+}
+
+impl<T: Read> Decoder<T> {
+    // reads the rest of the scan, split after every RST marker. The marker that ends the scan is
+    // left for next_marker, invalid markers are kept in the data for the entropy decoder to fail on
+    fn read_segments(&mut self) -> Result<(Vec<u8>, Vec<Segment>)> {
+        let mut data = Vec::new();
+        let mut segments = Vec::new();
+        let mut start = 0;
+        loop {
+            let marker = match self.read_data_byte()? {
+                Some(0xff) => {
+                    data.push(0xff);
+                    // 0xff 0x00 is a stuffed 0xff, anything else but fill bytes is a marker
+                    let mut next = self.read_data_byte()?;
+                    while next == Some(0xff) {
+                        data.push(0xff);
+                        next = self.read_data_byte()?;
+                    }
+                    match next {
+                        Some(b) => {
+                            data.push(b);
+                            if b < 0xc0 {
+                                continue;
+                            }
+                            Some(b)
+                        }
+                        None => None,
+                    }
+                }
+                Some(b) => {
+                    data.push(b);
+                    continue;
+                }
+                None => None,
+            };
+            segments.push(Segment {
+                start,
+                end: data.len(),
+                marker,
+            });
+            match marker {
+                Some(0xd0..=0xd7) => start = data.len(),
+                Some(m) => {
+                    self.unread_marker = Some(m);
+                    return Ok((data, segments));
+                }
+                None => return Ok((data, segments)),
+            }
+        }
+    }
+    // decodes the restart intervals of a sequential scan on several threads, each interval is
+    // entropy coded on its own so only the placement of the blocks depends on the others
+    pub(super) fn parse_intervals_parallel(
+        &mut self,
+        frame_indices: &[usize],
+        components: &[Component],
+        scan_x: u64,
+        scan_y: u64,
+    ) -> Result<()> {
+        let offset = self.reader.position;
+        let (data, segments) = self.read_segments()?;
+        let interval = self.restart_interval as u64;
+        let mcus = scan_x * scan_y;
+        let non_interleaved = components.len() == 1;
+        // the interval in every segment, RSTn ends the intervals numbered n modulo 8
+        let mut jobs: Vec<(u64, &Segment)> = Vec::new();
+        let mut index = 0;
+        for segment in segments.iter() {
+            if index * interval >= mcus {
+                // the marker that ended the last interval, or the one in the data of a scan without MCUs
+                let (marker, at) = match jobs.last() {
+                    Some((_, last)) => (last.marker, segment.start),
+                    None => (segment.marker, segment.end),
+                };
+                let marker = match marker {
+                    Some(m @ 0xd0..=0xd7) => m,
+                    _ => break,
+                };
+                if !self.lenient {
+                    return Err(Error::from(ErrorKind::UnknownMarker(marker)).at_offset(offset + at as u64));
+                }
+                let error = Error::from(ErrorKind::UnexpectedMarker(marker)).at_offset(offset + at as u64);
+                warn!("{}, skipped", error);
+                self.errors.push(error);
+                break;
+            }
+            jobs.push((index, segment));
+            index += match segment.marker {
+                Some(m @ 0xd0..=0xd7) => 1 + (m as u64 + 8 - 0xd0 - index % 8) % 8,
+                _ => 1,
+            };
+        }
+        info!("decoding {} restart intervals on {} threads", jobs.len(), self.threads);
+        let blocks = self.blocks();
+        let per_thread = jobs.len().div_ceil(self.threads).max(1);
+        let decoded: Vec<DecodedInterval> = thread::scope(|s| {
+            let handles: Vec<_> = jobs
+                .chunks(per_thread)
+                .map(|chunk| {
+                    let blocks = &blocks;
+                    let data = &data;
+                    s.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|&(index, segment)| {
+                                let count = interval.min(mcus - index * interval);
+                                let segment_data = &data[segment.start..segment.end];
+                                let segment_offset = offset + segment.start as u64;
+                                decode_interval(
+                                    blocks,
+                                    segment_data,
+                                    segment_offset,
+                                    components,
+                                    frame_indices,
+                                    index * interval,
+                                    count,
+                                    scan_x,
+                                )
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            // every thread is joined, the scope would panic on one that is left to it and panicked
+            let joined: Vec<_> = handles.into_iter().map(|h| h.join()).collect();
+            joined.into_iter().collect::<std::result::Result<Vec<_>, _>>()
+        })
+        .map_err(|_| Error::from(ErrorKind::InvalidSegment("decoder thread panicked".to_string())))?
+        .into_iter()
+        .flatten()
+        .collect();
+        let mut mcus_decoded = 0;
+        for (&(index, segment), result) in jobs.iter().zip(decoded) {
+            let first = index * interval;
+            let count = interval.min(mcus - first);
+            let mut blocks = result.blocks.iter();
+            'place: for mcu_ptr in first..first + count {
+                let (ix, iy) = (mcu_ptr % scan_x, mcu_ptr / scan_x);
+                for (i, c) in components.iter().enumerate() {
+                    let (blocks_h, blocks_v) = if non_interleaved { (1, 1) } else { (c.hi as u64, c.vi as u64) };
+                    for iv in 0..blocks_v {
+                        for ih in 0..blocks_h {
+                            match blocks.next() {
+                                Some(Some(block)) => {
+                                    self.store_block(frame_indices[i], ix * blocks_h + ih, iy * blocks_v + iv, block)
+                                }
+                                Some(None) => (),
+                                None => break 'place,
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some((mcu, error)) = result.error {
+                let mcu_ptr = first + mcu;
+                let error = error.at_mcu(mcu_ptr % scan_x, mcu_ptr / scan_x);
+                if !self.lenient {
+                    return Err(error);
+                }
+                mcus_decoded += mcu;
+                // nothing follows the end of the input, end_decode records the error and the MCUs
+                // left as it does for the serial decoder
+                if segment.marker.is_none() {
+                    self.mcus_lost += mcu_ptr - mcus_decoded;
+                    return Err(error);
+                }
+                warn!("{}, skipped the rest of the restart interval", error);
+                self.errors.push(error);
+            } else {
+                mcus_decoded += count;
+            }
+            let end = first + count;
+            let expected = 0xd0 + (index % 8) as u8;
+            if end < mcus && segment.marker != Some(expected) {
+                let kind = match segment.marker {
+                    Some(found) => ErrorKind::BadRestartMarker { expected, found },
+                    None => ErrorKind::Truncated,
+                };
+                let error = Error::from(kind).at_mcu(end % scan_x, end / scan_x).at_offset(offset + segment.end as u64);
+                if !self.lenient {
+                    return Err(error);
+                }
+                if segment.marker.is_none() {
+                    self.mcus_lost += end - mcus_decoded;
+                    return Err(error);
+                }
+                warn!("{}, skipped to the next restart interval", error);
+                self.errors.push(error);
+            }
+        }
+        if self.lenient {
+            self.mcus_lost += mcus - mcus_decoded;
+        }
+        Ok(())
+    }
+}
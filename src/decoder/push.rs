@@ -0,0 +1,190 @@
+use super::{Decoder, Result, Scan, ScanKind};
+use log::info;
+use std::io::{self, Read};
+
+/// the input fed to a push decode so far, minus what lies before the last checkpoint. Reading past
+/// the end fails with WouldBlock until the input is finished
+pub struct PushInput {
+    data: Vec<u8>,
+    // input offset of data[0]
+    base: u64,
+    position: usize,
+    finished: bool,
+}
+
+impl PushInput {
+    pub(crate) fn new() -> PushInput {
+        PushInput {
+            data: Vec::new(),
+            base: 0,
+            position: 0,
+            finished: false,
+        }
+    }
+    fn seek(&mut self, offset: u64) {
+        self.position = (offset - self.base) as usize;
+    }
+    // drops the data before offset once it is most of the buffer, so that the copying stays linear
+    fn discard(&mut self, offset: u64) {
+        let n = (offset - self.base) as usize;
+        if n > self.data.len() / 2 {
+            self.data.drain(..n);
+            self.base = offset;
+            self.position -= n;
+        }
+    }
+}
+
+impl Read for PushInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = &self.data[self.position..];
+        if available.is_empty() && !buf.is_empty() && !self.finished {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+// decoder state at the start of a marker segment or of an MCU row, for the blocks of a progressive
+// row the coefficients as they were before it, since decoding them again would refine them twice
+pub(super) struct Checkpoint {
+    position: u64,
+    unread_marker: Option<u8>,
+    scan: Option<Scan>,
+    // frame index, first block and the blocks
+    coeffs: Vec<(usize, usize, Vec<[i32; 64]>)>,
+    errors: usize,
+    mcus_lost: u64,
+}
+
+impl<T: Read> Decoder<T> {
+    pub(super) fn checkpoint(&mut self, scan: Option<&Scan>) {
+        let mut coeffs = Vec::new();
+        if let Some(scan) = scan.filter(|scan| matches!(scan.kind, ScanKind::Progressive(..))) {
+            let (mcu_x, _) = self.mcu_size();
+            let iy = scan.mcu_ptr / scan.scan_x;
+            let non_interleaved = scan.components.len() == 1;
+            for (c, &frame_index) in scan.components.iter().zip(scan.frame_indices.iter()) {
+                let blocks_v = if non_interleaved { 1 } else { c.vi as u64 };
+                let stride = mcu_x * c.hi as u64;
+                let blocks = &self.coeffs[frame_index];
+                let start = ((iy * blocks_v * stride) as usize).min(blocks.len());
+                let end = (start + (blocks_v * stride) as usize).min(blocks.len());
+                coeffs.push((frame_index, start, blocks[start..end].to_vec()));
+            }
+        }
+        self.checkpoint = Some(Checkpoint {
+            position: self.reader.position,
+            unread_marker: self.unread_marker,
+            scan: scan.cloned(),
+            coeffs,
+            errors: self.errors.len(),
+            mcus_lost: self.mcus_lost,
+        });
+    }
+    // returns to the last checkpoint, or the start of the input if there is none yet, and gives
+    // the input offset to continue reading from
+    fn restore_checkpoint(&mut self) -> u64 {
+        let checkpoint = match &self.checkpoint {
+            Some(checkpoint) => checkpoint,
+            None => {
+                self.reader.position = 0;
+                return 0;
+            }
+        };
+        self.unread_marker = checkpoint.unread_marker;
+        self.scan = checkpoint.scan.clone();
+        for (frame_index, start, blocks) in checkpoint.coeffs.iter() {
+            self.coeffs[*frame_index][*start..*start + blocks.len()].copy_from_slice(blocks);
+        }
+        self.errors.truncate(checkpoint.errors);
+        self.mcus_lost = checkpoint.mcus_lost;
+        self.reader.position = checkpoint.position;
+        checkpoint.position
+    }
+}
+
+/// how far a push decode got. mcu_rows counts the MCU rows decoded of the scan in progress
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Progress {
+    pub headers_ready: bool,
+    pub scans_complete: usize,
+    pub mcu_rows: u64,
+    pub done: bool,
+}
+
+/// decodes input that arrives in chunks of any size. Every chunk is decoded as far as it goes, and
+/// what did not fit an MCU row or a marker segment is decoded again with the next chunk
+pub struct PushDecoder {
+    decoder: Decoder<PushInput>,
+    done: bool,
+}
+
+impl PushDecoder {
+    pub fn new() -> PushDecoder {
+        PushDecoder::with_decoder(Decoder::new(PushInput::new()))
+    }
+    pub(crate) fn with_decoder(mut decoder: Decoder<PushInput>) -> PushDecoder {
+        decoder.push = true;
+        PushDecoder { decoder, done: false }
+    }
+    /// the decoder holds the image and header information decoded so far
+    pub fn decoder(&self) -> &Decoder<PushInput> {
+        &self.decoder
+    }
+    /// appends data to the input and decodes as far as it goes
+    pub fn feed(&mut self, data: &[u8]) -> Result<Progress> {
+        self.decoder.reader.inner.data.extend_from_slice(data);
+        self.run()
+    }
+    /// marks the end of the input, an image that is not complete by then is truncated
+    pub fn finish(&mut self) -> Result<Progress> {
+        self.decoder.reader.inner.finished = true;
+        self.run()
+    }
+    fn run(&mut self) -> Result<Progress> {
+        if self.done {
+            return Ok(self.progress());
+        }
+        match self.decoder.decode_markers() {
+            Err(e) if e.is_would_block() => {
+                let position = self.decoder.restore_checkpoint();
+                let input = &mut self.decoder.reader.inner;
+                input.seek(position);
+                input.discard(position);
+            }
+            result => {
+                self.done = true;
+                self.decoder.checkpoint = None;
+                self.decoder.end_decode(result)?;
+            }
+        }
+        Ok(self.progress())
+    }
+    /// how far the input fed so far was decoded
+    pub fn progress(&self) -> Progress {
+        let decoder = &self.decoder;
+        Progress {
+            headers_ready: !decoder.components.is_empty(),
+            scans_complete: decoder.scans_complete,
+            mcu_rows: decoder.scan.as_ref().map_or(0, |scan| scan.mcu_ptr / scan.scan_x),
+            done: self.done,
+        }
+    }
+    // a progressive image only shows the scans decoded so far after its coefficients are rendered,
+    // which decoding does by itself at the end
+    pub(crate) fn render(&mut self) {
+        if self.decoder.progressive && !self.done && self.decoder.render_progressive().is_err() {
+            info!("no quantization table to render the progressive image with yet");
+        }
+    }
+}
+
+impl Default for PushDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,213 @@
+mod decoder;
+
+pub use decoder::{
+    ColorMatrix, ColorSpace, Decoder, Error, ErrorKind, IdctMethod, Jfif, Plane, Progress, PushDecoder, PushInput,
+    Recovery, Scale, TableClass, Upsampling,
+};
+
+use std::io::Read;
+use std::ops::Range;
+
+/// the layout of the decoded pixels. Rgb565 is a little endian u16 per pixel, YCbCr8Planar is the
+/// full size Y, Cb and Cr planes one after the other, the others are packed in the order of their name.
+/// The 8-bit formats scale samples of other precisions to 8 bits, Gray16 and Rgb16 keep the precision
+/// of the frame in a little endian u16 per sample, so 12-bit images range from 0 to 4095
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PixelFormat {
+    Gray8,
+    Gray16,
+    Rgb8,
+    Rgb16,
+    Rgba8,
+    Bgr8,
+    Bgra8,
+    Rgb565,
+    Cmyk8,
+    YCbCr8Planar,
+}
+
+impl PixelFormat {
+    /// counting the bytes of every plane of a planar format
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Gray8 => 1,
+            PixelFormat::Gray16 | PixelFormat::Rgb565 => 2,
+            PixelFormat::Rgb8 | PixelFormat::Bgr8 | PixelFormat::YCbCr8Planar => 3,
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 | PixelFormat::Cmyk8 => 4,
+            PixelFormat::Rgb16 => 6,
+        }
+    }
+}
+
+/// decoded pixels, row by row without padding
+pub struct Image {
+    width: u16,
+    height: u16,
+    pixel_format: PixelFormat,
+    data: Vec<u8>,
+}
+
+impl Image {
+    pub fn get_width(&self) -> u16 {
+        self.width
+    }
+    pub fn get_height(&self) -> u16 {
+        self.height
+    }
+    pub fn get_pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl<T: Read> Decoder<T> {
+    /// natural format of the decoded color space, with 16-bit samples for frames of more than 8 bits
+    pub fn get_default_pixel_format(&self) -> PixelFormat {
+        match (self.get_color_space(), self.get_precision() > 8) {
+            (ColorSpace::Gray, false) => PixelFormat::Gray8,
+            (ColorSpace::Gray, true) => PixelFormat::Gray16,
+            (_, false) => PixelFormat::Rgb8,
+            (_, true) => PixelFormat::Rgb16,
+        }
+    }
+    /// the decoded image in pixel_format, complete once decode returned
+    pub fn get_image(&self, pixel_format: PixelFormat) -> Image {
+        self.get_rows(0..self.get_height(), pixel_format)
+    }
+    fn get_rows(&self, rows: Range<u16>, pixel_format: PixelFormat) -> Image {
+        let height = rows.len() as u16;
+        let data = match pixel_format {
+            PixelFormat::Gray8 => self.gray_rows(rows),
+            PixelFormat::Gray16 => self.gray16_rows(rows),
+            PixelFormat::Rgb8 => self.rgb_rows(rows, false, false),
+            PixelFormat::Rgb16 => self.rgb16_rows(rows),
+            PixelFormat::Rgba8 => self.rgb_rows(rows, false, true),
+            PixelFormat::Bgr8 => self.rgb_rows(rows, true, false),
+            PixelFormat::Bgra8 => self.rgb_rows(rows, true, true),
+            PixelFormat::Rgb565 => self.rgb565_rows(rows),
+            PixelFormat::Cmyk8 => self.cmyk_rows(rows),
+            PixelFormat::YCbCr8Planar => self.ycbcr_rows(rows),
+        };
+        Image {
+            width: self.get_width(),
+            height,
+            pixel_format,
+            data,
+        }
+    }
+    /// decodes the image by bands of rows instead of decode, returning the index of the first row
+    /// and the rows of the next band, None after the last one. Sequential images that have every
+    /// component in one scan are decoded a row of MCUs at a time, so that only one band of the image
+    /// is held in memory, other images come out as a single band once they are decoded
+    pub fn next_rows(&mut self, pixel_format: PixelFormat) -> Result<Option<(u16, Image)>, Error> {
+        let rows = self.decode_band()?;
+        Ok(rows.map(|rows| (rows.start, self.get_rows(rows, pixel_format))))
+    }
+}
+
+impl PushDecoder {
+    /// the image as far as it is decoded, samples not reached yet are gray
+    pub fn get_image(&mut self, pixel_format: PixelFormat) -> Image {
+        self.render();
+        self.decoder().get_image(pixel_format)
+    }
+}
+
+/// the settings of a decoder, to build one or decode an image with them in one call
+pub struct DecoderBuilder {
+    idct_method: IdctMethod,
+    scale: Scale,
+    upsampling: Upsampling,
+    color_matrix: ColorMatrix,
+    pixel_format: Option<PixelFormat>,
+    crop: Option<(u16, u16, u16, u16)>,
+    lenient: bool,
+    threads: usize,
+}
+
+impl DecoderBuilder {
+    pub fn new() -> DecoderBuilder {
+        DecoderBuilder {
+            idct_method: IdctMethod::Integer,
+            scale: Scale::Full,
+            upsampling: Upsampling::Fast,
+            color_matrix: ColorMatrix::Bt601,
+            pixel_format: None,
+            crop: None,
+            lenient: false,
+            threads: 1,
+        }
+    }
+    pub fn idct_method(mut self, idct_method: IdctMethod) -> DecoderBuilder {
+        self.idct_method = idct_method;
+        self
+    }
+    pub fn scale(mut self, scale: Scale) -> DecoderBuilder {
+        self.scale = scale;
+        self
+    }
+    pub fn upsampling(mut self, upsampling: Upsampling) -> DecoderBuilder {
+        self.upsampling = upsampling;
+        self
+    }
+    /// see Decoder::set_color_matrix
+    pub fn color_matrix(mut self, color_matrix: ColorMatrix) -> DecoderBuilder {
+        self.color_matrix = color_matrix;
+        self
+    }
+    /// format of the image returned by decode, the default pixel format of the image if not set
+    pub fn pixel_format(mut self, pixel_format: PixelFormat) -> DecoderBuilder {
+        self.pixel_format = Some(pixel_format);
+        self
+    }
+    /// see Decoder::set_crop
+    pub fn crop(mut self, x: u16, y: u16, width: u16, height: u16) -> DecoderBuilder {
+        self.crop = Some((x, y, width, height));
+        self
+    }
+    /// decode corrupt or truncated input as far as possible instead of failing, see Decoder::set_lenient
+    pub fn lenient(mut self, lenient: bool) -> DecoderBuilder {
+        self.lenient = lenient;
+        self
+    }
+    /// see Decoder::set_threads
+    pub fn threads(mut self, threads: usize) -> DecoderBuilder {
+        self.threads = threads;
+        self
+    }
+    pub fn build<T: Read>(&self, reader: T) -> Decoder<T> {
+        let mut decoder = Decoder::new(reader);
+        decoder.set_idct_method(self.idct_method);
+        decoder.set_scale(self.scale);
+        decoder.set_upsampling(self.upsampling);
+        decoder.set_color_matrix(self.color_matrix);
+        if let Some((x, y, width, height)) = self.crop {
+            decoder.set_crop(x, y, width, height);
+        }
+        decoder.set_lenient(self.lenient);
+        decoder.set_threads(self.threads);
+        decoder
+    }
+    /// a decoder with these settings that is fed the input in chunks
+    pub fn build_push(&self) -> PushDecoder {
+        PushDecoder::with_decoder(self.build(PushInput::new()))
+    }
+    /// decodes the whole image from reader in the pixel format set
+    pub fn decode<T: Read>(&self, reader: T) -> Result<Image, Error> {
+        let mut decoder = self.build(reader);
+        decoder.decode()?;
+        let pixel_format = self.pixel_format.unwrap_or_else(|| decoder.get_default_pixel_format());
+        Ok(decoder.get_image(pixel_format))
+    }
+}
+
+impl Default for DecoderBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,10 +1,8 @@
-mod decoder;
-
-use env_logger;
+use jpeg_parser2::{ColorMatrix, Decoder as JpegDecoder, DecoderBuilder, IdctMethod, PixelFormat, PushDecoder, Scale, Upsampling};
 use log::{Log, Metadata, Record, info, warn, LevelFilter};
 use std::fs::File;
 use std::env;
-use std::io::{BufReader,BufWriter};
+use std::io::{BufReader,BufWriter,Read,Write};
 use std::sync::Mutex;
 use std::sync::Arc;
 use std::cell::RefCell;
@@ -40,6 +38,8 @@ struct Result {
 #[wasm_bindgen]
 pub struct Decoder {
     results: HashMap<usize, Result>,
+    // push decodes of files that are still being read
+    streams: HashMap<usize, PushDecoder>,
     ptr: usize,
     log_string: Arc<Mutex<RefCell<String>>>,
 }
@@ -53,28 +53,73 @@ impl Decoder {
         log::set_max_level(LevelFilter::Info);
         Decoder{
             results: HashMap::new(),
+            streams: HashMap::new(),
             ptr: 0,
             log_string: s.clone(),
         }
     }
     pub fn parse(&mut self, data: &[u8]) -> usize {
         *(self.log_string.lock().unwrap().borrow_mut()) = "".to_string();
-        let mut decoder = decoder::Decoder::new(BufReader::new(data));
+        let mut decoder = DecoderBuilder::new().build(BufReader::new(data));
         let decode_res = decoder.decode();
-        match decode_res  {
-            Err(e) => warn!("error occured while decoding {}", e),
-            _ => (),
+        if let Err(e) = decode_res {
+            warn!("error occured while decoding {}", e)
         }
+        let image = decoder.get_image(PixelFormat::Rgba8);
         let result = Result{
-            width: decoder.get_width() as usize,
-            height: decoder.get_height() as usize,
+            width: image.get_width() as usize,
+            height: image.get_height() as usize,
             log: self.log_string.lock().unwrap().borrow().clone(),
-            pix: decoder.get_rgb_vec(true),
+            pix: image.into_data(),
         };
         self.ptr += 1;
         self.results.insert(self.ptr, result);
         self.ptr
     }
+    pub fn begin_stream(&mut self) -> usize {
+        *(self.log_string.lock().unwrap().borrow_mut()) = "".to_string();
+        self.ptr += 1;
+        self.streams.insert(self.ptr, DecoderBuilder::new().build_push());
+        self.ptr
+    }
+    // returns the number of MCU rows decoded of the current scan, or -1 while the size of the image is not known yet
+    // and for a stream that is not open
+    pub fn push_chunk(&mut self, stream: usize, data: &[u8]) -> i32 {
+        let decoder = match self.streams.get_mut(&stream) {
+            Some(decoder) => decoder,
+            None => return -1,
+        };
+        match decoder.feed(data) {
+            Ok(progress) if progress.headers_ready => progress.mcu_rows as i32,
+            Ok(_) => -1,
+            Err(e) => {
+                warn!("error occured while decoding {}", e);
+                -1
+            }
+        }
+    }
+    pub fn finish_stream(&mut self, stream: usize) {
+        if let Some(Err(e)) = self.streams.get_mut(&stream).map(|decoder| decoder.finish()) {
+            warn!("error occured while decoding {}", e)
+        }
+    }
+    // the image decoded so far as a result handle, the stream stays open until end_stream. A stream that is not
+    // open gives an empty image
+    pub fn get_stream_image(&mut self, stream: usize) -> usize {
+        let image = self.streams.get_mut(&stream).map(|decoder| decoder.get_image(PixelFormat::Rgba8));
+        let result = Result{
+            width: image.as_ref().map_or(0, |image| image.get_width() as usize),
+            height: image.as_ref().map_or(0, |image| image.get_height() as usize),
+            log: self.log_string.lock().unwrap().borrow().clone(),
+            pix: image.map_or(Vec::new(), |image| image.into_data()),
+        };
+        self.ptr += 1;
+        self.results.insert(self.ptr, result);
+        self.ptr
+    }
+    pub fn end_stream(&mut self, stream: usize) {
+        self.streams.remove(&stream);
+    }
     pub fn get_height(&self, handle:usize) -> usize {
         self.results.get(&handle).unwrap().height
     }
@@ -92,19 +137,144 @@ impl Decoder {
     }
 }
 
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub fn main(){
     if cfg!(target_arch="wasm32") {
         return
     }
     env_logger::init();
     let path = env::args().nth(1).unwrap();
+    let options: Vec<String> = env::args().skip(2).collect();
+    let has_option = |name: &str| options.iter().any(|o| o == name);
     info!("path {}", path);
-    let mut decoder = decoder::Decoder::new(BufReader::new(File::open(path).unwrap()));
+    let mut builder = DecoderBuilder::new();
+    if has_option("--lenient") {
+        builder = builder.lenient(true);
+    }
+    if has_option("--float-idct") {
+        builder = builder.idct_method(IdctMethod::Float);
+    }
+    if has_option("--fancy") {
+        builder = builder.upsampling(Upsampling::Fancy);
+    }
+    if let Some(threads) = options.iter().find_map(|o| o.strip_prefix("--threads=")) {
+        builder = builder.threads(threads.parse().unwrap_or(1));
+    }
+    if let Some(scale) = options.iter().find_map(|o| o.strip_prefix("--scale=")) {
+        builder = builder.scale(match scale {
+            "1/2" => Scale::Half,
+            "1/4" => Scale::Quarter,
+            "1/8" => Scale::Eighth,
+            _ => Scale::Full,
+        });
+    }
+    if let Some(matrix) = options.iter().find_map(|o| o.strip_prefix("--color-matrix=")) {
+        builder = builder.color_matrix(match matrix {
+            "bt601-limited" => ColorMatrix::Bt601Limited,
+            "bt709" => ColorMatrix::Bt709,
+            _ => ColorMatrix::Bt601,
+        });
+    }
+    // --crop=WxH+X+Y
+    if let Some(crop) = options.iter().find_map(|o| o.strip_prefix("--crop=")) {
+        let v: Vec<u16> = crop.split(['x', '+']).map(|v| v.parse().unwrap_or(0)).collect();
+        if v.len() == 4 {
+            builder = builder.crop(v[2], v[3], v[0], v[1]);
+        }
+    }
+    // --chunk=N feeds the file to a push decoder N bytes at a time
+    if let Some(chunk) = options.iter().find_map(|o| o.strip_prefix("--chunk=")) {
+        let mut decoder = builder.build_push();
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        for part in data.chunks(chunk.parse().unwrap_or(4096).max(1)) {
+            match decoder.feed(part) {
+                Ok(progress) => info!("{:?}", progress),
+                Err(e) => warn!("error occured while decoding {}", e),
+            }
+        }
+        if let Err(e) = decoder.finish() {
+            warn!("error occured while decoding {}", e)
+        }
+        write_output(decoder.decoder(), &options);
+        return;
+    }
+    // --rows writes the image band by band while it is decoded, with 8-bit samples
+    if has_option("--rows") {
+        let mut decoder = builder.build(BufReader::new(File::open(path).unwrap()));
+        let (magic, pixel_format) = if has_option("--gray") { ("P5", PixelFormat::Gray8) } else { ("P6", PixelFormat::Rgb8) };
+        // the file is created with the first band, so that it is not left empty when there is none
+        let mut output = None;
+        loop {
+            match decoder.next_rows(pixel_format) {
+                Ok(Some((_, rows))) => {
+                    let w = output.get_or_insert_with(|| {
+                        let mut w = BufWriter::new(File::create("output.ppm").unwrap());
+                        write!(w, "{}\n{} {}\n255\n", magic, decoder.get_width(), decoder.get_height()).unwrap();
+                        w
+                    });
+                    w.write_all(rows.get_data()).unwrap();
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    warn!("error occured while decoding {}", e);
+                    break;
+                }
+            }
+        }
+        if output.is_none() {
+            warn!("no rows were decoded, output.ppm is not written");
+        }
+        return;
+    }
+    let mut decoder = builder.build(BufReader::new(File::open(path).unwrap()));
     let decode_res = decoder.decode();
-    match decode_res  {
-        Err(e) => warn!("error occured while decoding {}", e),
-        _ => (),
+    if let Err(e) = decode_res {
+        warn!("error occured while decoding {}", e)
+    }
+    write_output(&decoder, &options);
+}
+
+// --raw=FORMAT writes the pixels in that format to output.raw instead
+fn write_output<T: Read>(decoder: &JpegDecoder<T>, options: &[String]) {
+    let has_option = |name: &str| options.iter().any(|o| o == name);
+    if let Some(format) = options.iter().find_map(|o| o.strip_prefix("--raw=")) {
+        let pixel_format = match format {
+            "gray8" => PixelFormat::Gray8,
+            "gray16" => PixelFormat::Gray16,
+            "rgb16" => PixelFormat::Rgb16,
+            "rgba8" => PixelFormat::Rgba8,
+            "bgr8" => PixelFormat::Bgr8,
+            "bgra8" => PixelFormat::Bgra8,
+            "rgb565" => PixelFormat::Rgb565,
+            "cmyk8" => PixelFormat::Cmyk8,
+            "ycbcr8planar" => PixelFormat::YCbCr8Planar,
+            _ => PixelFormat::Rgb8,
+        };
+        let image = decoder.get_image(pixel_format);
+        info!("{}x{} {:?}", image.get_width(), image.get_height(), pixel_format);
+        File::create("output.raw").unwrap().write_all(image.get_data()).unwrap();
+        return;
+    }
+    // --y4m writes the YCbCr planes as they are to output.y4m
+    if has_option("--y4m") {
+        let mut w = BufWriter::new(File::create("output.y4m").unwrap());
+        if let Err(e) = decoder.outputy4m(&mut w) {
+            warn!("{}", e);
+        }
+        return;
     }
     let mut w = BufWriter::new(File::create("output.ppm").unwrap());
-    decoder.outputppm(&mut w).unwrap();
+    if has_option("--gray") {
+        decoder.outputpgm(&mut w).unwrap();
+    } else if has_option("--cmyk") {
+        decoder.outputpam(&mut w).unwrap();
+    } else {
+        decoder.outputppm(&mut w).unwrap();
+    }
 }
@@ -0,0 +1,85 @@
+mod common;
+
+use common::arith::lossless_scan;
+use common::{dac, dri, sof, sos, Frame, EOI, SOI};
+use jpeg_parser2::{Decoder, PixelFormat};
+use std::io::Cursor;
+
+fn decode(data: &[u8], pixel_format: PixelFormat) -> Vec<u8> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    decoder.get_image(pixel_format).into_data()
+}
+
+// blocks with a scattering of AC coefficients of both signs and up to 9 bits, past the AC
+// conditioning band and up to the last coefficient, and DC differences of all sizes
+fn block(c: usize, bx: usize, by: usize) -> [i32; 64] {
+    let b = (by * 5 + bx) * 3 + c;
+    let mut block = [0; 64];
+    block[0] = [0, 3, -1, 60, -200, 7, 7, -30][b % 8];
+    for (k, coeff) in block.iter_mut().enumerate().skip(1) {
+        if (b * 7 + k * 3).is_multiple_of(11) || (k == 63 && b.is_multiple_of(4)) {
+            *coeff = (((b + k) % 9) as i32 - 4) * [1, 3, 50][k % 3];
+        }
+    }
+    block
+}
+
+#[test]
+fn sequential_gray() {
+    let frame = Frame::gray(40, 24, |bx, by| block(0, bx, by));
+    assert_eq!(decode(&frame.arithmetic(2), PixelFormat::Gray8), decode(&frame.baseline(2), PixelFormat::Gray8));
+}
+
+#[test]
+fn sequential_color() {
+    let frame = Frame::new(40, 24, &[(2, 2), (1, 1), (1, 1)], block);
+    assert_eq!(decode(&frame.arithmetic(2), PixelFormat::Rgb8), decode(&frame.baseline(2), PixelFormat::Rgb8));
+}
+
+// a flat block decodes to 128 + DC at quantization 8
+#[test]
+fn sequential_samples() {
+    let frame = Frame::gray(24, 8, |bx, _| common::flat([5, -100, 127][bx]));
+    let expected: Vec<u8> = (0..8).flat_map(|_| (0..24).map(|x| [133, 28, 255][x / 8])).collect();
+    assert_eq!(decode(&frame.arithmetic(8), PixelFormat::Gray8), expected);
+}
+
+#[test]
+fn conditioning_and_restarts() {
+    let frame = Frame::new(40, 24, &[(2, 2), (1, 1), (1, 1)], block);
+    for ((l, u), k) in [((2, 4), 2), ((0, 0), 63), ((5, 15), 1)] {
+        let data = [
+            frame.headers(0xc9, 2),
+            dac(&[(0, 0, u << 4 | l), (1, 0, k as u8)]),
+            dri(2),
+            sos(&[(1, 0, 0), (2, 0, 0), (3, 0, 0)], 0, 63, 0, 0),
+            frame.arithmetic_scan((l, u), k, 2),
+            EOI.to_vec(),
+        ]
+        .concat();
+        let expected = decode(&frame.baseline(2), PixelFormat::Rgb8);
+        assert_eq!(decode(&data, PixelFormat::Rgb8), expected, "L={} U={} Kx={}", l, u, k);
+    }
+}
+
+#[test]
+fn lossless() {
+    let (width, height) = (11, 6);
+    let samples: Vec<i32> = (0..width * height).map(|i| ((i * 37 + i / width * 101) % 256) as i32).collect();
+    for predictor in 1..=7 {
+        for (l, u) in [(0, 1), (1, 3)] {
+            let data = [
+                SOI.to_vec(),
+                sof(0xcb, 8, width as u16, height as u16, &[(1, 1, 0)]),
+                dac(&[(0, 0, u << 4 | l)]),
+                sos(&[(1, 0, 0)], predictor, 0, 0, 0),
+                lossless_scan(&samples, width, predictor, 128, (l, u)),
+                EOI.to_vec(),
+            ]
+            .concat();
+            let expected: Vec<u8> = samples.iter().map(|&s| s as u8).collect();
+            assert_eq!(decode(&data, PixelFormat::Gray8), expected, "predictor {} L={} U={}", predictor, l, u);
+        }
+    }
+}
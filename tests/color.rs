@@ -0,0 +1,110 @@
+mod common;
+
+use common::{dht, dqt, flat, segment, sos, Frame, EOI, SOI};
+use jpeg_parser2::{ColorMatrix, ColorSpace, Decoder, DecoderBuilder, PixelFormat};
+use std::io::Cursor;
+
+// (Y, Cb, Cr) or (R, G, B) of three flat blocks side by side
+const SAMPLES: [[i32; 3]; 3] = [[100, 90, 170], [16, 128, 128], [235, 200, 60]];
+
+// a 24x8 frame of three components with the samples at quantization 8
+fn frame() -> Frame {
+    Frame::new(24, 8, &[(1, 1), (1, 1), (1, 1)], |c, bx, _| flat(SAMPLES[bx][c] - 128))
+}
+
+fn jfif() -> Vec<u8> {
+    segment(0xe0, b"JFIF\0\x01\x01\x00\x00\x01\x00\x01\x00\x00")
+}
+
+fn adobe(transform: u8) -> Vec<u8> {
+    segment(0xee, &[b"Adobe\x00\x64\x00\x00\x00\x00".as_slice(), &[transform]].concat())
+}
+
+// the frame with the component ids and the segments before the frame header
+fn image(ids: &[u8; 3], segments: &[Vec<u8>]) -> Vec<u8> {
+    let frame = frame();
+    let mut header = vec![8, 0, 8, 0, 24, 3];
+    ids.iter().for_each(|&id| header.extend_from_slice(&[id, 0x11, 0]));
+    let components: Vec<_> = ids.iter().map(|&id| (id, 0, 0)).collect();
+    [
+        SOI.to_vec(),
+        segments.concat(),
+        dqt(0, 8),
+        segment(0xc0, &header),
+        dht(0, 0),
+        dht(1, 0),
+        sos(&components, 0, 63, 0, 0),
+        frame.sequential_scan(0),
+        EOI.to_vec(),
+    ]
+    .concat()
+}
+
+fn decode(data: &[u8], color_matrix: ColorMatrix) -> (ColorSpace, Vec<u8>) {
+    let mut decoder = DecoderBuilder::new().color_matrix(color_matrix).build(Cursor::new(data));
+    decoder.decode().unwrap();
+    (decoder.get_color_space(), decoder.get_image(PixelFormat::Rgb8).into_data())
+}
+
+// the pixels of the three blocks given by their RGB
+fn pixels(rgb: impl Fn([i32; 3]) -> [f64; 3]) -> Vec<[i32; 3]> {
+    let blocks: Vec<_> = SAMPLES.iter().map(|&s| rgb(s).map(|v| v.round().clamp(0., 255.) as i32)).collect();
+    (0..8 * 24).map(|i| blocks[i % 24 / 8]).collect()
+}
+
+// within one of the expected values, for the rounding of the conversion
+fn assert_close(image: &[u8], expected: &[[i32; 3]]) {
+    for (i, (rgb, expected)) in image.chunks(3).zip(expected).enumerate() {
+        for (&v, &e) in rgb.iter().zip(expected) {
+            assert!((v as i32 - e).abs() <= 1, "pixel {}: {:?} for {:?}", i, rgb, expected);
+        }
+    }
+}
+
+#[test]
+fn rgb_frames() {
+    let expected = pixels(|s| s.map(|v| v as f64));
+    for (ids, segments) in [(b"RGB", vec![]), (b"\x01\x02\x03", vec![adobe(0)]), (b"RGB", vec![adobe(0)])] {
+        let (color_space, image) = decode(&image(ids, &segments), ColorMatrix::Bt601);
+        assert_eq!(color_space, ColorSpace::Rgb);
+        assert_close(&image, &expected);
+    }
+}
+
+#[test]
+fn ycbcr_frames() {
+    // a JFIF segment or an Adobe transform wins over ids 'R', 'G' and 'B'
+    for (ids, segments) in [(b"\x01\x02\x03", vec![]), (b"RGB", vec![jfif()]), (b"RGB", vec![adobe(1)])] {
+        let (color_space, _) = decode(&image(ids, &segments), ColorMatrix::Bt601);
+        assert_eq!(color_space, ColorSpace::YCbCr);
+    }
+}
+
+// the conversion of full range or limited range samples with the coefficients of a matrix
+fn convert(limited: bool, (red_cr, green_cb, green_cr, blue_cb): (f64, f64, f64, f64)) -> Vec<[i32; 3]> {
+    pixels(|[y, cb, cr]| {
+        let (y, cb, cr) = (y as f64, (cb - 128) as f64, (cr - 128) as f64);
+        let (y, cb, cr) = if limited { ((y - 16.) * 255. / 219., cb * 255. / 224., cr * 255. / 224.) } else { (y, cb, cr) };
+        [y + red_cr * cr, y - green_cb * cb - green_cr * cr, y + blue_cb * cb]
+    })
+}
+
+#[test]
+fn color_matrices() {
+    let data = image(b"\x01\x02\x03", &[jfif()]);
+    let bt601 = (1.402, 0.34414, 0.71414, 1.772);
+    let bt709 = (1.5748, 0.18732, 0.46812, 1.8556);
+    for (matrix, expected) in [
+        (ColorMatrix::Bt601, convert(false, bt601)),
+        (ColorMatrix::Bt601Limited, convert(true, bt601)),
+        (ColorMatrix::Bt709, convert(true, bt709)),
+    ] {
+        let (_, image) = decode(&data, matrix);
+        assert_close(&image, &expected);
+    }
+    // the limited range black and white of the second block stretch to the full range
+    let mut decoder = Decoder::new(Cursor::new(&data));
+    decoder.set_color_matrix(ColorMatrix::Bt709);
+    decoder.decode().unwrap();
+    assert_eq!(decoder.get_image(PixelFormat::Gray8).into_data()[8..16], [0; 8]);
+}
@@ -0,0 +1,302 @@
+// the QM coder of the arithmetic coded processes as libjpeg encodes it (Annex D)
+use super::{predict, Frame, EOI};
+
+// Qe_Value, Next_Index_LPS, Next_Index_MPS and Switch_MPS of Table D.2
+const QE: [(u32, u8, u8, u8); 114] = [
+    (0x5a1d, 1, 1, 1), (0x2586, 14, 2, 0), (0x1114, 16, 3, 0), (0x080b, 18, 4, 0), (0x03d8, 20, 5, 0),
+    (0x01da, 23, 6, 0), (0x00e5, 25, 7, 0), (0x006f, 28, 8, 0), (0x0036, 30, 9, 0), (0x001a, 33, 10, 0),
+    (0x000d, 35, 11, 0), (0x0006, 9, 12, 0), (0x0003, 10, 13, 0), (0x0001, 12, 13, 0), (0x5a7f, 15, 15, 1),
+    (0x3f25, 36, 16, 0), (0x2cf2, 38, 17, 0), (0x207c, 39, 18, 0), (0x17b9, 40, 19, 0), (0x1182, 42, 20, 0),
+    (0x0cef, 43, 21, 0), (0x09a1, 45, 22, 0), (0x072f, 46, 23, 0), (0x055c, 48, 24, 0), (0x0406, 49, 25, 0),
+    (0x0303, 51, 26, 0), (0x0240, 52, 27, 0), (0x01b1, 54, 28, 0), (0x0144, 56, 29, 0), (0x00f5, 57, 30, 0),
+    (0x00b7, 59, 31, 0), (0x008a, 60, 32, 0), (0x0068, 62, 33, 0), (0x004e, 63, 34, 0), (0x003b, 32, 35, 0),
+    (0x002c, 33, 9, 0), (0x5ae1, 37, 37, 1), (0x484c, 64, 38, 0), (0x3a0d, 65, 39, 0), (0x2ef1, 67, 40, 0),
+    (0x261f, 68, 41, 0), (0x1f33, 69, 42, 0), (0x19a8, 70, 43, 0), (0x1518, 72, 44, 0), (0x1177, 73, 45, 0),
+    (0x0e74, 74, 46, 0), (0x0bfb, 75, 47, 0), (0x09f8, 77, 48, 0), (0x0861, 78, 49, 0), (0x0706, 79, 50, 0),
+    (0x05cd, 48, 51, 0), (0x04de, 50, 52, 0), (0x040f, 50, 53, 0), (0x0363, 51, 54, 0), (0x02d4, 52, 55, 0),
+    (0x025c, 53, 56, 0), (0x01f8, 54, 57, 0), (0x01a4, 55, 58, 0), (0x0160, 56, 59, 0), (0x0125, 57, 60, 0),
+    (0x00f6, 58, 61, 0), (0x00cb, 59, 62, 0), (0x00ab, 61, 63, 0), (0x008f, 61, 32, 0), (0x5b12, 65, 65, 1),
+    (0x4d04, 80, 66, 0), (0x412c, 81, 67, 0), (0x37d8, 82, 68, 0), (0x2fe8, 83, 69, 0), (0x293c, 84, 70, 0),
+    (0x2379, 86, 71, 0), (0x1edf, 87, 72, 0), (0x1aa9, 87, 73, 0), (0x174e, 72, 74, 0), (0x1424, 72, 75, 0),
+    (0x119c, 74, 76, 0), (0x0f6b, 74, 77, 0), (0x0d51, 75, 78, 0), (0x0bb6, 77, 79, 0), (0x0a40, 77, 48, 0),
+    (0x5832, 80, 81, 1), (0x4d1c, 88, 82, 0), (0x438e, 89, 83, 0), (0x3bdd, 90, 84, 0), (0x34ee, 91, 85, 0),
+    (0x2eae, 92, 86, 0), (0x299a, 93, 87, 0), (0x2516, 86, 71, 0), (0x5570, 88, 89, 1), (0x4ca9, 95, 90, 0),
+    (0x44d9, 96, 91, 0), (0x3e22, 97, 92, 0), (0x3824, 99, 93, 0), (0x32b4, 99, 94, 0), (0x2e17, 93, 86, 0),
+    (0x56a8, 95, 96, 1), (0x4f46, 101, 97, 0), (0x47e5, 102, 98, 0), (0x41cf, 103, 99, 0), (0x3c3d, 104, 100, 0),
+    (0x375e, 99, 93, 0), (0x5231, 105, 102, 0), (0x4c0f, 106, 103, 0), (0x4639, 107, 104, 0), (0x415e, 103, 99, 0),
+    (0x5627, 105, 106, 1), (0x50e7, 108, 107, 0), (0x4b85, 109, 103, 0), (0x5597, 110, 109, 0), (0x504f, 111, 107, 0),
+    (0x5a10, 110, 111, 1), (0x5522, 112, 109, 0), (0x59eb, 112, 111, 1), (0x5a1d, 113, 113, 0),
+];
+
+pub struct ArithEncoder {
+    data: Vec<u8>,
+    a: u32,
+    c: u32,
+    ct: u32,
+    // stacked 0xff bytes and pending zeros
+    sc: u32,
+    zc: u32,
+    buffer: Option<u8>,
+}
+
+impl ArithEncoder {
+    pub fn new() -> ArithEncoder {
+        ArithEncoder { data: Vec::new(), a: 0x10000, c: 0, ct: 11, sc: 0, zc: 0, buffer: None }
+    }
+    fn emit(&mut self, byte: u8) {
+        self.data.push(byte);
+        if byte == 0xff {
+            self.data.push(0);
+        }
+    }
+    fn emit_zeros(&mut self) {
+        for _ in 0..self.zc {
+            self.data.push(0);
+        }
+        self.zc = 0;
+    }
+    fn emit_stacked(&mut self) {
+        if self.sc > 0 {
+            self.emit_zeros();
+            for _ in 0..self.sc {
+                self.emit(0xff);
+            }
+            self.sc = 0;
+        }
+    }
+    // a byte that carried into the buffered one, which turns the stacked 0xff bytes into zeros
+    fn carry(&mut self) {
+        if let Some(buffer) = self.buffer {
+            self.emit_zeros();
+            self.emit(buffer + 1);
+        }
+        self.zc += self.sc;
+        self.sc = 0;
+    }
+    fn flush_buffer(&mut self) {
+        match self.buffer {
+            Some(0) => self.zc += 1,
+            Some(buffer) => {
+                self.emit_zeros();
+                self.emit(buffer);
+            }
+            None => (),
+        }
+        self.emit_stacked();
+    }
+    // codes val with the statistics bin st, the index of its state and the MPS in the top bit
+    pub fn encode(&mut self, st: &mut u8, val: bool) {
+        let (qe, next_lps, next_mps, switch) = QE[(*st & 0x7f) as usize];
+        self.a -= qe;
+        if val != (*st >> 7 == 1) {
+            if self.a >= qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *st = (*st & 0x80) ^ (next_lps | switch << 7);
+        } else {
+            if self.a >= 0x8000 {
+                return;
+            }
+            if self.a < qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *st = (*st & 0x80) ^ next_mps;
+        }
+        while self.a < 0x8000 {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+            if self.ct == 0 {
+                let temp = self.c >> 19;
+                if temp > 0xff {
+                    self.carry();
+                    self.buffer = Some(temp as u8);
+                } else if temp == 0xff {
+                    self.sc += 1;
+                } else {
+                    self.flush_buffer();
+                    self.buffer = Some(temp as u8);
+                }
+                self.c &= 0x7ffff;
+                self.ct += 8;
+            }
+        }
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        let temp = (self.a - 1 + self.c) & 0xffff0000;
+        self.c = if temp < self.c { temp + 0x8000 } else { temp };
+        self.c <<= self.ct;
+        if self.c & 0xf8000000 != 0 {
+            self.carry();
+        } else {
+            self.flush_buffer();
+        }
+        // the final bytes are left out if they are 0
+        if self.c & 0x7fff800 != 0 {
+            self.emit_zeros();
+            self.emit((self.c >> 19) as u8);
+            if self.c & 0x7f800 != 0 {
+                self.emit((self.c >> 11) as u8);
+            }
+        }
+        self.data
+    }
+    // the magnitude category and bits of v + 1 (F.1.4.1, Figures F.8 and F.9), with the unary digits
+    // of the category in the bins given by bin and the bits in the bin 14 after the last digit.
+    // Returns the category bound m that conditions the next DC difference
+    fn magnitude(&mut self, stats: &mut [u8], bin: impl Fn(usize) -> usize, v: u32) -> u32 {
+        let mut digits = 0;
+        let mut m = 0;
+        if v != 0 {
+            m = 1;
+            self.encode(&mut stats[bin(0)], true);
+            digits = 1;
+            let mut v2 = v >> 1;
+            while v2 != 0 {
+                self.encode(&mut stats[bin(digits)], true);
+                m <<= 1;
+                digits += 1;
+                v2 >>= 1;
+            }
+        }
+        self.encode(&mut stats[bin(digits)], false);
+        let st = bin(digits) + 14;
+        let mut bit = m >> 1;
+        while bit != 0 {
+            self.encode(&mut stats[st], v & bit != 0);
+            bit >>= 1;
+        }
+        m
+    }
+}
+
+impl Frame {
+    // the entropy coded data of an arithmetic coded sequential scan of all components, with the DC
+    // conditioning (l, u) and the AC conditioning k, and an RST marker after every restart_interval
+    // MCUs if it is not 0 (F.1.4)
+    pub fn arithmetic_scan(&self, (l, u): (u8, u8), k: usize, restart_interval: usize) -> Vec<u8> {
+        let (mcus_x, mcus_y) = self.mcus(&self.factors());
+        let components = self.components.len();
+        let mut data = Vec::new();
+        let mut encoder = ArithEncoder::new();
+        let mut dc_stats = [0; 64];
+        let mut ac_stats = [0; 256];
+        let mut fixed = 113;
+        let mut prev_dc = vec![0; components];
+        let mut context = vec![0; components];
+        for mcu in 0..mcus_x * mcus_y {
+            if restart_interval != 0 && mcu != 0 && mcu % restart_interval == 0 {
+                data.extend(std::mem::replace(&mut encoder, ArithEncoder::new()).finish());
+                data.extend_from_slice(&[0xff, 0xd0 + ((mcu / restart_interval - 1) % 8) as u8]);
+                dc_stats = [0; 64];
+                ac_stats = [0; 256];
+                prev_dc.iter_mut().for_each(|dc| *dc = 0);
+                context.iter_mut().for_each(|c| *c = 0);
+            }
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for (i, c) in self.components.iter().enumerate() {
+                let (h, v) = if components == 1 { (1, 1) } else { (c.h as usize, c.v as usize) };
+                let blocks_x = mcus_x * c.h as usize;
+                for iv in 0..v {
+                    for ih in 0..h {
+                        let block = &c.blocks[(my * v + iv) * blocks_x + mx * h + ih];
+                        // DC, with its statistics conditioned on the previous difference
+                        let s0 = context[i];
+                        let diff = block[0] - prev_dc[i];
+                        prev_dc[i] = block[0];
+                        encoder.encode(&mut dc_stats[s0], diff != 0);
+                        if diff == 0 {
+                            context[i] = 0;
+                        } else {
+                            encoder.encode(&mut dc_stats[s0 + 1], diff < 0);
+                            let sp = s0 + 2 + (diff < 0) as usize;
+                            let bin = |digit| if digit == 0 { sp } else { 20 + digit - 1 };
+                            let m = encoder.magnitude(&mut dc_stats, bin, diff.unsigned_abs() - 1);
+                            context[i] = if m < (1 << l) >> 1 {
+                                0
+                            } else if m > (1 << u) >> 1 {
+                                12 + 4 * (diff < 0) as usize
+                            } else {
+                                4 + 4 * (diff < 0) as usize
+                            };
+                        }
+                        // AC, up to the last nonzero coefficient and an EOB unless it is the 63rd
+                        let end = (1..64).rev().find(|&k| block[k] != 0).unwrap_or(0);
+                        let mut n = 1;
+                        while n <= end {
+                            let mut st = 3 * (n - 1);
+                            encoder.encode(&mut ac_stats[st], false);
+                            while block[n] == 0 {
+                                encoder.encode(&mut ac_stats[st + 1], false);
+                                st += 3;
+                                n += 1;
+                            }
+                            encoder.encode(&mut ac_stats[st + 1], true);
+                            // the sign has a fixed probability, the first two digits share a bin
+                            encoder.encode(&mut fixed, block[n] < 0);
+                            let x2 = if n <= k { 189 } else { 217 };
+                            let bin = |digit| if digit < 2 { st + 2 } else { x2 + digit - 2 };
+                            encoder.magnitude(&mut ac_stats, bin, block[n].unsigned_abs() - 1);
+                            n += 1;
+                        }
+                        if n <= 63 {
+                            encoder.encode(&mut ac_stats[3 * (n - 1)], true);
+                        }
+                    }
+                }
+            }
+        }
+        data.extend(encoder.finish());
+        data
+    }
+    // an arithmetic coded sequential (SOF9) stream of the frame with the default conditioning
+    pub fn arithmetic(&self, q: u16) -> Vec<u8> {
+        let ids: Vec<_> = (1..=self.components.len() as u8).map(|id| (id, 0, 0)).collect();
+        [
+            self.headers(0xc9, q),
+            super::sos(&ids, 0, 63, 0, 0),
+            self.arithmetic_scan((0, 1), 5, 0),
+            EOI.to_vec(),
+        ]
+        .concat()
+    }
+}
+
+// the entropy coded data of an arithmetic coded lossless scan of one component of the given width
+// after the point transform, with the first sample predicted as initial and the conditioning (l, u)
+// (H.1.4.3)
+pub fn lossless_scan(samples: &[i32], width: usize, predictor: u8, initial: i32, (l, u): (u8, u8)) -> Vec<u8> {
+    let mut encoder = ArithEncoder::new();
+    let mut stats = [0; 158];
+    let mut diffs = vec![0; samples.len()];
+    // the category of the difference to the left or above (H.1.4.3.1)
+    let category = |d: i32| {
+        if d.abs() <= (1 << l) >> 1 {
+            0
+        } else if d.abs() <= (1 << u) >> 1 {
+            1 + (d < 0) as usize
+        } else {
+            3 + (d < 0) as usize
+        }
+    };
+    for (i, &sample) in samples.iter().enumerate() {
+        let (x, y) = (i % width, i / width);
+        let diff = (sample - predict(samples, width, x, y, predictor, initial)) as i16 as i32;
+        diffs[i] = diff;
+        let da = if x > 0 { diffs[i - 1] } else { 0 };
+        let db = if y > 0 { diffs[i - width] } else { 0 };
+        let s0 = 4 * (5 * category(da) + category(db));
+        encoder.encode(&mut stats[s0], diff != 0);
+        if diff == 0 {
+            continue;
+        }
+        encoder.encode(&mut stats[s0 + 1], diff < 0);
+        let sp = s0 + 2 + (diff < 0) as usize;
+        let x1 = if category(db) >= 3 { 129 } else { 100 };
+        let bin = |digit| if digit == 0 { sp } else { x1 + digit - 1 };
+        encoder.magnitude(&mut stats, bin, diff.unsigned_abs() - 1);
+    }
+    encoder.finish()
+}
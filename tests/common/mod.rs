@@ -0,0 +1,408 @@
+// builders for small hand made JPEG streams and an encoder for their entropy coded data. Every
+// Huffman table codes the symbols 0 to 254 with 8-bit codes equal to the symbol, so that any
+// coefficient can be coded without building a table for it
+#![allow(dead_code)]
+
+pub mod arith;
+
+pub const SOI: [u8; 2] = [0xff, 0xd8];
+pub const EOI: [u8; 2] = [0xff, 0xd9];
+
+pub fn segment(marker: u8, content: &[u8]) -> Vec<u8> {
+    let mut data = vec![0xff, marker];
+    data.extend_from_slice(&((content.len() + 2) as u16).to_be_bytes());
+    data.extend_from_slice(content);
+    data
+}
+
+// a table of 64 times q, with 16-bit entries if q does not fit 8 bits
+pub fn dqt(id: u8, q: u16) -> Vec<u8> {
+    if q > 255 {
+        let entries: Vec<u8> = (0..64).flat_map(|_| q.to_be_bytes()).collect();
+        segment(0xdb, &[[0x10 | id].as_slice(), &entries].concat())
+    } else {
+        segment(0xdb, &[[id].as_slice(), &[q as u8; 64]].concat())
+    }
+}
+
+// components are (hi, vi, tq) and numbered from 1
+pub fn sof(marker: u8, precision: u8, width: u16, height: u16, components: &[(u8, u8, u8)]) -> Vec<u8> {
+    let mut content = vec![precision];
+    content.extend_from_slice(&height.to_be_bytes());
+    content.extend_from_slice(&width.to_be_bytes());
+    content.push(components.len() as u8);
+    for (i, &(h, v, tq)) in components.iter().enumerate() {
+        content.extend_from_slice(&[i as u8 + 1, h << 4 | v, tq]);
+    }
+    segment(marker, &content)
+}
+
+// the table of the given class and destination with 8-bit codes for the symbols 0 to 254
+pub fn dht(class: u8, id: u8) -> Vec<u8> {
+    let mut content = vec![class << 4 | id];
+    let mut counts = [0; 16];
+    counts[7] = 255;
+    content.extend_from_slice(&counts);
+    content.extend(0..255);
+    segment(0xc4, &content)
+}
+
+// components are (id, td, ta)
+pub fn sos(components: &[(u8, u8, u8)], ss: u8, se: u8, ah: u8, al: u8) -> Vec<u8> {
+    let mut content = vec![components.len() as u8];
+    for &(id, td, ta) in components {
+        content.extend_from_slice(&[id, td << 4 | ta]);
+    }
+    content.extend_from_slice(&[ss, se, ah << 4 | al]);
+    segment(0xda, &content)
+}
+
+// conditioning (class, destination, value) of arithmetic coding, U << 4 | L for DC and Kx for AC
+pub fn dac(conditioning: &[(u8, u8, u8)]) -> Vec<u8> {
+    let content: Vec<u8> = conditioning.iter().flat_map(|&(class, id, value)| [class << 4 | id, value]).collect();
+    segment(0xcc, &content)
+}
+
+pub fn dri(interval: u16) -> Vec<u8> {
+    segment(0xdd, &interval.to_be_bytes())
+}
+
+// entropy coded data, padded with 1 bits and with every 0xff byte followed by a stuffed 0
+pub struct BitWriter {
+    data: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter { data: Vec::new(), acc: 0, bits: 0 }
+    }
+    pub fn put(&mut self, value: u32, len: u32) {
+        self.acc = self.acc << len | (value as u64 & ((1 << len) - 1));
+        self.bits += len;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            let byte = (self.acc >> self.bits) as u8;
+            self.data.push(byte);
+            if byte == 0xff {
+                self.data.push(0);
+            }
+        }
+    }
+    pub fn symbol(&mut self, symbol: u8) {
+        self.put(symbol as u32, 8);
+    }
+    // the symbol of run and the size of value, followed by the bits of value (F.1.2.1)
+    pub fn value(&mut self, run: u8, value: i32) {
+        let size = 32 - value.unsigned_abs().leading_zeros();
+        self.symbol(run << 4 | size as u8);
+        let bits = if value < 0 { value - 1 } else { value };
+        self.put(bits as u32, size);
+    }
+    // the DC difference and the AC coefficients of a sequential block in zigzag order
+    pub fn block(&mut self, diff: i32, coeffs: &[i32; 64]) {
+        self.value(0, diff);
+        let mut run = 0;
+        for &c in &coeffs[1..] {
+            if c == 0 {
+                run += 1;
+                continue;
+            }
+            while run > 15 {
+                self.symbol(0xf0);
+                run -= 16;
+            }
+            self.value(run, c);
+            run = 0;
+        }
+        if run > 0 {
+            self.symbol(0x00);
+        }
+    }
+    // a run of blocks with no coefficients left in the band of a progressive scan (G.1.2.2)
+    pub fn eobrun(&mut self, run: u32) {
+        let n = 31 - run.leading_zeros();
+        self.symbol((n << 4) as u8);
+        self.put(run - (1 << n), n);
+    }
+    pub fn finish(mut self) -> Vec<u8> {
+        let pad = (8 - self.bits % 8) % 8;
+        self.put((1 << pad) - 1, pad);
+        self.data
+    }
+}
+
+// a component of a frame with its blocks of quantized coefficients in zigzag order, in raster
+// order over the component padded to whole MCUs
+pub struct Component {
+    pub h: u8,
+    pub v: u8,
+    pub blocks: Vec<[i32; 64]>,
+}
+
+pub struct Frame {
+    pub width: u16,
+    pub height: u16,
+    pub precision: u8,
+    pub components: Vec<Component>,
+}
+
+impl Frame {
+    // a frame of one component with the block at (bx, by) given by block
+    pub fn gray(width: u16, height: u16, block: impl Fn(usize, usize) -> [i32; 64]) -> Frame {
+        Frame::new(width, height, &[(1, 1)], |_, bx, by| block(bx, by))
+    }
+    // a frame of components sampled at factors, with the block at (bx, by) of component c given by block
+    pub fn new(
+        width: u16,
+        height: u16,
+        factors: &[(u8, u8)],
+        block: impl Fn(usize, usize, usize) -> [i32; 64],
+    ) -> Frame {
+        let mut frame = Frame { width, height, precision: 8, components: Vec::new() };
+        let (mcus_x, mcus_y) = frame.mcus(factors);
+        for (c, &(h, v)) in factors.iter().enumerate() {
+            let (blocks_x, blocks_y) = (mcus_x * h as usize, mcus_y * v as usize);
+            let blocks = (0..blocks_x * blocks_y).map(|i| block(c, i % blocks_x, i / blocks_x)).collect();
+            frame.components.push(Component { h, v, blocks });
+        }
+        frame
+    }
+    // MCUs of an interleaved scan, or blocks of a single component
+    fn mcus(&self, factors: &[(u8, u8)]) -> (usize, usize) {
+        let max_h = factors.iter().map(|f| f.0).max().unwrap() as usize;
+        let max_v = factors.iter().map(|f| f.1).max().unwrap() as usize;
+        let (width, height) = (self.width as usize, self.height as usize);
+        (width.div_ceil(8 * max_h), height.div_ceil(8 * max_v))
+    }
+    fn factors(&self) -> Vec<(u8, u8)> {
+        self.components.iter().map(|c| (c.h, c.v)).collect()
+    }
+    // the headers of a frame of the given SOF marker with every component using quantization
+    // table 0 of q, and the DC and AC tables 0
+    pub fn headers(&self, marker: u8, q: u16) -> Vec<u8> {
+        let components: Vec<_> = self.components.iter().map(|c| (c.h, c.v, 0)).collect();
+        [
+            SOI.to_vec(),
+            dqt(0, q),
+            sof(marker, self.precision, self.width, self.height, &components),
+            dht(0, 0),
+            dht(1, 0),
+        ]
+        .concat()
+    }
+    // the entropy coded data of a sequential scan of all components, with an RST marker after
+    // every restart_interval MCUs if it is not 0
+    pub fn sequential_scan(&self, restart_interval: usize) -> Vec<u8> {
+        let (mcus_x, mcus_y) = self.mcus(&self.factors());
+        let mut prev_dc = vec![0; self.components.len()];
+        let mut data = Vec::new();
+        let mut writer = BitWriter::new();
+        for mcu in 0..mcus_x * mcus_y {
+            if restart_interval != 0 && mcu != 0 && mcu % restart_interval == 0 {
+                data.extend(std::mem::replace(&mut writer, BitWriter::new()).finish());
+                data.extend_from_slice(&[0xff, 0xd0 + ((mcu / restart_interval - 1) % 8) as u8]);
+                prev_dc.iter_mut().for_each(|dc| *dc = 0);
+            }
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for (i, c) in self.components.iter().enumerate() {
+                let (h, v) = if self.components.len() == 1 { (1, 1) } else { (c.h as usize, c.v as usize) };
+                let blocks_x = mcus_x * c.h as usize;
+                for iv in 0..v {
+                    for ih in 0..h {
+                        let block = &c.blocks[(my * v + iv) * blocks_x + mx * h + ih];
+                        writer.block(block[0] - prev_dc[i], block);
+                        prev_dc[i] = block[0];
+                    }
+                }
+            }
+        }
+        data.extend(writer.finish());
+        data
+    }
+    // the blocks of a scan of the components in coding order, with the index of their component
+    fn scan_blocks(&self, components: &[usize]) -> Vec<(usize, &[i32; 64])> {
+        let (mcus_x, mcus_y) = self.mcus(&self.factors());
+        let mut blocks = Vec::new();
+        if let [c] = *components {
+            // a single component scan covers the blocks of the component without the MCU padding
+            let component = &self.components[c];
+            let (max_h, max_v) = self.components.iter().fold((0, 0), |(h, v), c| (h.max(c.h), v.max(c.v)));
+            let width = (self.width as usize * component.h as usize).div_ceil(max_h as usize);
+            let height = (self.height as usize * component.v as usize).div_ceil(max_v as usize);
+            let blocks_x = mcus_x * component.h as usize;
+            for by in 0..height.div_ceil(8) {
+                for bx in 0..width.div_ceil(8) {
+                    blocks.push((c, &component.blocks[by * blocks_x + bx]));
+                }
+            }
+            return blocks;
+        }
+        for mcu in 0..mcus_x * mcus_y {
+            let (mx, my) = (mcu % mcus_x, mcu / mcus_x);
+            for &c in components {
+                let component = &self.components[c];
+                let (h, v) = (component.h as usize, component.v as usize);
+                for iv in 0..v {
+                    for ih in 0..h {
+                        blocks.push((c, &component.blocks[(my * v + iv) * mcus_x * h + mx * h + ih]));
+                    }
+                }
+            }
+        }
+        blocks
+    }
+    // the entropy coded data of a sequential scan of one component
+    pub fn component_scan(&self, component: usize) -> Vec<u8> {
+        let mut prev_dc = 0;
+        let mut writer = BitWriter::new();
+        for (_, block) in self.scan_blocks(&[component]) {
+            writer.block(block[0] - prev_dc, block);
+            prev_dc = block[0];
+        }
+        writer.finish()
+    }
+    // the entropy coded data of the first DC scan of a progressive frame with point transform al
+    pub fn dc_scan(&self, components: &[usize], al: u8) -> Vec<u8> {
+        let mut prev_dc = vec![0; self.components.len()];
+        let mut writer = BitWriter::new();
+        for (c, block) in self.scan_blocks(components) {
+            let dc = block[0] >> al;
+            writer.value(0, dc - prev_dc[c]);
+            prev_dc[c] = dc;
+        }
+        writer.finish()
+    }
+    // a DC refinement scan, one bit per block
+    pub fn dc_refine_scan(&self, components: &[usize], al: u8) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        for (_, block) in self.scan_blocks(components) {
+            writer.put((block[0] >> al) as u32 & 1, 1);
+        }
+        writer.finish()
+    }
+    // the first AC scan of the band ss..=se of a component with point transform al (G.1.2.2)
+    pub fn ac_scan(&self, component: usize, ss: usize, se: usize, al: u8) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        let mut eobrun = 0;
+        for (_, block) in self.scan_blocks(&[component]) {
+            let mut run = 0;
+            for &coeff in &block[ss..=se] {
+                // the point transform of the magnitude, as libjpeg does
+                let value = coeff.signum() * (coeff.abs() >> al);
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                if eobrun > 0 {
+                    writer.eobrun(eobrun);
+                    eobrun = 0;
+                }
+                while run > 15 {
+                    writer.symbol(0xf0);
+                    run -= 16;
+                }
+                writer.value(run, value);
+                run = 0;
+            }
+            if run > 0 {
+                eobrun += 1;
+            }
+        }
+        if eobrun > 0 {
+            writer.eobrun(eobrun);
+        }
+        writer.finish()
+    }
+    // an AC refinement scan of the band ss..=se for bit al (G.1.2.3), coded as libjpeg does with the
+    // correction bits of coefficients that were already nonzero after the next new one or the EOB run
+    pub fn ac_refine_scan(&self, component: usize, ss: usize, se: usize, al: u8) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        let mut eobrun = 0;
+        // correction bits that go with the pending EOB run
+        let mut eob_bits = Vec::new();
+        for (_, block) in self.scan_blocks(&[component]) {
+            // the last coefficient that becomes nonzero in this scan
+            let last_new = (ss..=se).rev().find(|&k| block[k].abs() >> al == 1);
+            let mut run = 0;
+            let mut bits = Vec::new();
+            for (k, &coeff) in block.iter().enumerate().take(se + 1).skip(ss) {
+                let value = coeff.abs() >> al;
+                if value == 0 {
+                    run += 1;
+                    continue;
+                }
+                // a ZRL before a new coefficient is coded at the nonzero one that follows 16 zeros
+                while run > 15 && last_new.is_some_and(|last| k <= last) {
+                    if eobrun > 0 {
+                        writer.eobrun(eobrun);
+                        eob_bits.drain(..).for_each(|bit| writer.put(bit, 1));
+                        eobrun = 0;
+                    }
+                    writer.symbol(0xf0);
+                    run -= 16;
+                    bits.drain(..).for_each(|bit| writer.put(bit, 1));
+                }
+                if value > 1 {
+                    bits.push(value as u32 & 1);
+                    continue;
+                }
+                if eobrun > 0 {
+                    writer.eobrun(eobrun);
+                    eob_bits.drain(..).for_each(|bit| writer.put(bit, 1));
+                    eobrun = 0;
+                }
+                writer.symbol((run << 4) as u8 | 1);
+                writer.put((coeff > 0) as u32, 1);
+                bits.drain(..).for_each(|bit| writer.put(bit, 1));
+                run = 0;
+            }
+            if run > 0 || !bits.is_empty() {
+                eobrun += 1;
+                eob_bits.append(&mut bits);
+            }
+        }
+        if eobrun > 0 {
+            writer.eobrun(eobrun);
+            eob_bits.drain(..).for_each(|bit| writer.put(bit, 1));
+        }
+        writer.finish()
+    }
+    // a baseline (SOF0) stream of the frame, or extended sequential (SOF1) above 8 bits
+    pub fn baseline(&self, q: u16) -> Vec<u8> {
+        let marker = if self.precision > 8 { 0xc1 } else { 0xc0 };
+        let ids: Vec<_> = (1..=self.components.len() as u8).map(|id| (id, 0, 0)).collect();
+        [self.headers(marker, q), sos(&ids, 0, 63, 0, 0), self.sequential_scan(0), EOI.to_vec()].concat()
+    }
+}
+
+// the prediction of sample (x, y) of a component of the given width from the samples before it,
+// with initial for the first sample (H.1.2.1)
+pub fn predict(s: &[i32], width: usize, x: usize, y: usize, predictor: u8, initial: i32) -> i32 {
+    let at = |x: usize, y: usize| s[y * width + x];
+    match (x, y) {
+        (0, 0) => initial,
+        (_, 0) => at(x - 1, 0),
+        (0, _) => at(0, y - 1),
+        _ => {
+            let (ra, rb, rc) = (at(x - 1, y), at(x, y - 1), at(x - 1, y - 1));
+            match predictor {
+                1 => ra,
+                2 => rb,
+                3 => rc,
+                4 => ra + rb - rc,
+                5 => ra + ((rb - rc) >> 1),
+                6 => rb + ((ra - rc) >> 1),
+                _ => (ra + rb) / 2,
+            }
+        }
+    }
+}
+
+// a block of only a DC coefficient
+pub fn flat(dc: i32) -> [i32; 64] {
+    let mut block = [0; 64];
+    block[0] = dc;
+    block
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use common::Frame;
+use jpeg_parser2::{DecoderBuilder, PixelFormat, Scale};
+use std::io::Cursor;
+
+fn block(c: usize, bx: usize, by: usize) -> [i32; 64] {
+    let b = (by * 6 + bx) * 3 + c;
+    let mut block = [0; 64];
+    block[0] = (b % 13) as i32 * 5 - 30;
+    block[2] = (b % 7) as i32 - 3;
+    block[4] = (b % 5) as i32 * 2 - 4;
+    block
+}
+
+fn decode(data: &[u8], builder: &DecoderBuilder, pixel_format: PixelFormat) -> (u16, u16, Vec<u8>) {
+    let mut decoder = builder.build(Cursor::new(data));
+    decoder.decode().unwrap();
+    let image = decoder.get_image(pixel_format);
+    (image.get_width(), image.get_height(), image.into_data())
+}
+
+// the rectangle of a full image of the given width with bpp bytes per pixel
+fn rectangle(image: &[u8], width: usize, bpp: usize, (x, y, w, h): (usize, usize, usize, usize)) -> Vec<u8> {
+    (y..y + h).flat_map(|row| image[(row * width + x) * bpp..(row * width + x + w) * bpp].to_vec()).collect()
+}
+
+#[test]
+fn rectangles() {
+    let gray = Frame::gray(45, 30, |bx, by| block(0, bx, by)).baseline(2);
+    let color = Frame::new(45, 30, &[(1, 1), (1, 1), (1, 1)], block).baseline(2);
+    let subsampled = Frame::new(45, 30, &[(2, 2), (1, 1), (1, 1)], block).baseline(2);
+    let images = [(gray, PixelFormat::Gray8, 1), (color, PixelFormat::Rgb8, 3), (subsampled, PixelFormat::Rgb8, 3)];
+    for (data, pixel_format, bpp) in images {
+        let (_, _, full) = decode(&data, &DecoderBuilder::new(), pixel_format);
+        // inside a block, across blocks, at the partial blocks of the edges and the whole image
+        for rect in [(2, 3, 4, 2), (5, 6, 20, 17), (40, 25, 5, 5), (0, 0, 45, 30)] {
+            let (x, y, w, h) = rect;
+            let builder = DecoderBuilder::new().crop(x as u16, y as u16, w as u16, h as u16);
+            let expected = (w as u16, h as u16, rectangle(&full, 45, bpp, rect));
+            assert_eq!(decode(&data, &builder, pixel_format), expected, "{:?}", rect);
+        }
+    }
+}
+
+#[test]
+fn clipped_to_the_image() {
+    let data = Frame::gray(45, 30, |bx, by| block(0, bx, by)).baseline(2);
+    let (_, _, full) = decode(&data, &DecoderBuilder::new(), PixelFormat::Gray8);
+    let builder = DecoderBuilder::new().crop(30, 20, 100, 100);
+    assert_eq!(decode(&data, &builder, PixelFormat::Gray8), (15, 10, rectangle(&full, 45, 1, (30, 20, 15, 10))));
+}
+
+#[test]
+fn scaled() {
+    // the rectangle is in pixels of the scaled image
+    let data = Frame::gray(45, 30, |bx, by| block(0, bx, by)).baseline(2);
+    let half = DecoderBuilder::new().scale(Scale::Half);
+    let (width, _, full) = decode(&data, &half, PixelFormat::Gray8);
+    assert_eq!(width, 23);
+    let builder = DecoderBuilder::new().scale(Scale::Half).crop(3, 5, 11, 9);
+    assert_eq!(decode(&data, &builder, PixelFormat::Gray8), (11, 9, rectangle(&full, 23, 1, (3, 5, 11, 9))));
+}
+
+#[test]
+fn rows_of_a_rectangle() {
+    let data = Frame::new(45, 30, &[(2, 2), (1, 1), (1, 1)], block).baseline(2);
+    let builder = DecoderBuilder::new().crop(7, 9, 30, 20);
+    let (_, _, expected) = decode(&data, &builder, PixelFormat::Rgb8);
+    let mut decoder = builder.build(Cursor::new(&data));
+    let mut rows = Vec::new();
+    while let Some((_, band)) = decoder.next_rows(PixelFormat::Rgb8).unwrap() {
+        rows.extend_from_slice(band.get_data());
+    }
+    assert_eq!(rows, expected);
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{flat, Frame};
+use jpeg_parser2::{Decoder, ErrorKind, PixelFormat};
+use std::io::Cursor;
+
+// a 16x8 color image of two MCUs with the frame header repeated before the scan
+fn duplicated_sof() -> Vec<u8> {
+    let frame = Frame::new(16, 8, &[(1, 1), (1, 1), (1, 1)], |c, bx, _| flat(if c == 0 { bx as i32 * 4 } else { 0 }));
+    let data = frame.baseline(8);
+    // the SOF0 segment of three components is 19 bytes
+    let sof = data.windows(2).position(|w| w == [0xff, 0xc0]).unwrap();
+    [&data[..sof + 19], &data[sof..]].concat()
+}
+
+#[test]
+fn second_frame_header() {
+    let data = duplicated_sof();
+    let err = Decoder::new(Cursor::new(&data)).decode().unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidSegment(_)));
+
+    let mut decoder = Decoder::new(Cursor::new(&data));
+    decoder.set_lenient(true);
+    assert!(decoder.decode().is_ok());
+    // decoding ends at the second header, before the scan
+    let recovery = decoder.get_recovery().unwrap();
+    assert_eq!(recovery.errors.len(), 1);
+    assert_eq!((recovery.mcus_recovered, recovery.mcus_total), (0, 2));
+    let data = decoder.get_image(PixelFormat::Rgb8).into_data();
+    assert_eq!(data, vec![128; 16 * 8 * 3]);
+}
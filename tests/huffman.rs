@@ -0,0 +1,60 @@
+mod common;
+
+use common::{flat, segment, sos, Frame, EOI};
+use jpeg_parser2::{Decoder, ErrorKind, PixelFormat};
+use std::io::Cursor;
+
+// a 32x8 gray image of four blocks, 128, 129, 130 and 131 in turn
+fn frame() -> Frame {
+    Frame::gray(32, 8, |bx, _| flat(bx as i32 * 8))
+}
+
+// the image with the extra table dht after the tables it is coded with
+fn image_with(dht: &[u8]) -> Vec<u8> {
+    let frame = frame();
+    [frame.headers(0xc0, 1), dht.to_vec(), sos(&[(1, 0, 0)], 0, 63, 0, 0), frame.sequential_scan(0), EOI.to_vec()]
+        .concat()
+}
+
+fn expected() -> Vec<u8> {
+    (0..8).flat_map(|_| (0..32).map(|x| 128 + x / 8)).collect()
+}
+
+// fails strictly, and ends a lenient decode before the scan
+fn check_invalid(dht: &[u8]) {
+    let data = image_with(&segment(0xc4, dht));
+    let err = Decoder::new(Cursor::new(&data)).decode().unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::InvalidSegment(_)));
+
+    let mut decoder = Decoder::new(Cursor::new(&data));
+    decoder.set_lenient(true);
+    assert!(decoder.decode().is_ok());
+    let recovery = decoder.get_recovery().unwrap();
+    assert_eq!(recovery.errors.len(), 1);
+    assert!(matches!(recovery.errors[0].kind(), ErrorKind::InvalidSegment(_)));
+    assert_eq!((recovery.mcus_recovered, recovery.mcus_total), (0, 4));
+    assert_eq!(decoder.get_image(PixelFormat::Gray8).into_data(), vec![128; 32 * 8]);
+}
+
+#[test]
+fn codes_of_one_length() {
+    let mut decoder = Decoder::new(Cursor::new(image_with(&[])));
+    decoder.decode().unwrap();
+    assert_eq!(decoder.get_image(PixelFormat::Gray8).into_data(), expected());
+}
+
+#[test]
+fn oversubscribed_dht() {
+    // three codes of length 1
+    let mut dht = vec![0x11, 3];
+    dht.extend_from_slice(&[0; 15]);
+    dht.extend_from_slice(&[0, 1, 2]);
+    check_invalid(&dht);
+}
+
+#[test]
+fn dht_with_too_many_values() {
+    let mut dht = vec![0x11];
+    dht.extend_from_slice(&[0xff; 16]);
+    check_invalid(&dht);
+}
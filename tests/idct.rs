@@ -0,0 +1,69 @@
+mod common;
+
+use common::Frame;
+use jpeg_parser2::{Decoder, IdctMethod, PixelFormat};
+use std::f64::consts::PI;
+use std::io::Cursor;
+
+// natural order index of the coefficients in zigzag order
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13, 6, 7, 14, 21,
+    28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61,
+    54, 47, 55, 62, 63,
+];
+
+// coefficients of every frequency that keep the samples within range
+fn block(bx: usize, by: usize) -> [i32; 64] {
+    let b = by * 4 + bx;
+    let mut block = [0; 64];
+    block[0] = b as i32 * 9 - 60;
+    for (k, coeff) in block.iter_mut().enumerate().skip(1) {
+        if (b + k).is_multiple_of(b % 3 + 1) {
+            *coeff = ((b * 13 + k * 7) % 15) as i32 - 7;
+        }
+    }
+    block
+}
+
+// the samples of a block with quantization q by the IDCT formula of A.3.3 in floating point
+fn reference(coeffs: &[i32; 64], q: i32) -> [u8; 64] {
+    let mut natural = [0.0; 64];
+    for (k, &coeff) in coeffs.iter().enumerate() {
+        natural[ZIGZAG[k]] = (coeff * q) as f64;
+    }
+    let c = |u: usize| if u == 0 { 1.0 / 2f64.sqrt() } else { 1.0 };
+    let mut samples = [0; 64];
+    for y in 0..8 {
+        for x in 0..8 {
+            let mut sum = 0.0;
+            for v in 0..8 {
+                for u in 0..8 {
+                    let cos_x = ((2 * x + 1) as f64 * u as f64 * PI / 16.0).cos();
+                    let cos_y = ((2 * y + 1) as f64 * v as f64 * PI / 16.0).cos();
+                    sum += c(u) * c(v) * natural[v * 8 + u] * cos_x * cos_y;
+                }
+            }
+            samples[y * 8 + x] = (sum / 4.0 + 128.0).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    samples
+}
+
+#[test]
+fn integer_and_float_match_reference() {
+    let (width, height, q) = (32, 16, 3);
+    let frame = Frame::gray(width, height, block);
+    let data = frame.baseline(q);
+    for idct_method in [IdctMethod::Integer, IdctMethod::Float] {
+        let mut decoder = Decoder::new(Cursor::new(&data));
+        decoder.set_idct_method(idct_method);
+        decoder.decode().unwrap();
+        let image = decoder.get_image(PixelFormat::Gray8).into_data();
+        for (i, &sample) in image.iter().enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            let expected = reference(&block(x / 8, y / 8), q as i32)[(y % 8) * 8 + x % 8];
+            let diff = (sample as i32 - expected as i32).abs();
+            assert!(diff <= 1, "{:?} at ({}, {}): {} for {}", idct_method, x, y, sample, expected);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use jpeg_parser2::{Decoder, DecoderBuilder, ErrorKind, PixelFormat, Recovery};
+use std::io::Cursor;
+
+// the kinds of the recorded errors, the MCUs recovered and the MCUs in total
+type Summary = (Vec<String>, u64, u64);
+
+fn segment(marker: u8, content: &[u8]) -> Vec<u8> {
+    let mut data = vec![0xff, marker];
+    data.extend_from_slice(&((content.len() + 2) as u16).to_be_bytes());
+    data.extend_from_slice(content);
+    data
+}
+
+// a 32x8 gray baseline image of 4 MCUs with a restart interval of one MCU, every MCU coded as
+// size 0 DC and EOB except those listed in corrupt, coded as four ZRL runs past the end of the block
+fn image(corrupt: &[usize]) -> Vec<u8> {
+    let mut data = vec![0xff, 0xd8];
+    data.extend(segment(0xdb, &[[0].as_slice(), &[1; 64]].concat()));
+    data.extend(segment(0xc0, &[8, 0, 8, 0, 32, 1, 1, 0x11, 0]));
+    // DC: 0 -> size 0, AC: 00 -> EOB, 01 -> ZRL, 10 -> run 0 size 1
+    let mut dc = vec![0x00, 1];
+    dc.extend_from_slice(&[0; 15]);
+    dc.push(0);
+    data.extend(segment(0xc4, &dc));
+    let mut ac = vec![0x10, 0, 3];
+    ac.extend_from_slice(&[0; 14]);
+    ac.extend_from_slice(&[0x00, 0xf0, 0x01]);
+    data.extend(segment(0xc4, &ac));
+    data.extend(segment(0xdd, &[0, 1]));
+    data.extend(segment(0xda, &[1, 1, 0x00, 0, 63, 0]));
+    for mcu in 0..4 {
+        if corrupt.contains(&mcu) {
+            data.extend_from_slice(&[0x2a, 0xff, 0x00]);
+        } else {
+            data.push(0x1f);
+        }
+        if mcu < 3 {
+            data.extend_from_slice(&[0xff, 0xd0 + mcu as u8]);
+        }
+    }
+    data.extend_from_slice(&[0xff, 0xd9]);
+    data
+}
+
+fn summary(recovery: &Recovery) -> Summary {
+    let kinds = recovery.errors.iter().map(|e| format!("{:?}", e.kind())).collect();
+    (kinds, recovery.mcus_recovered, recovery.mcus_total)
+}
+
+fn decode(data: &[u8], threads: usize) -> Summary {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.set_lenient(true);
+    decoder.set_threads(threads);
+    assert!(decoder.decode().is_ok());
+    summary(decoder.get_recovery().unwrap())
+}
+
+fn decode_rows(data: &[u8]) -> Summary {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.set_lenient(true);
+    while decoder.next_rows(PixelFormat::Gray8).unwrap().is_some() {}
+    summary(decoder.get_recovery().unwrap())
+}
+
+fn decode_pushed(data: &[u8]) -> Summary {
+    let mut decoder = DecoderBuilder::new().lenient(true).build_push();
+    for chunk in data.chunks(5) {
+        assert!(decoder.feed(chunk).is_ok());
+    }
+    assert!(decoder.finish().is_ok());
+    summary(decoder.decoder().get_recovery().unwrap())
+}
+
+fn check(data: &[u8], kinds: &[ErrorKind], mcus_recovered: u64) {
+    let kinds = kinds.iter().map(|kind| format!("{:?}", kind)).collect();
+    let expected = (kinds, mcus_recovered, 4);
+    assert_eq!(decode(data, 1), expected);
+    assert_eq!(decode(data, 4), expected);
+    assert_eq!(decode_rows(data), expected);
+    assert_eq!(decode_pushed(data), expected);
+}
+
+#[test]
+fn intact() {
+    let data = image(&[]);
+    let mut decoder = Decoder::new(Cursor::new(&data));
+    decoder.set_lenient(true);
+    assert!(decoder.decode().is_ok());
+    assert!(decoder.get_recovery().is_none());
+}
+
+#[test]
+fn corrupt_coefficient_run() {
+    check(&image(&[1]), &[ErrorKind::InvalidCoefficient], 3);
+}
+
+#[test]
+fn truncated_scan() {
+    let data = image(&[]);
+    // cut after the second MCU and its restart marker
+    let end = data.len() - 6;
+    check(&data[..end], &[ErrorKind::Truncated], 2);
+}
+
+#[test]
+fn truncated_scan_after_corrupt_interval() {
+    let data = image(&[1]);
+    let end = data.len() - 6;
+    check(&data[..end], &[ErrorKind::InvalidCoefficient, ErrorKind::Truncated], 1);
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use common::{dht, predict, sof, sos, BitWriter, EOI, SOI};
+use jpeg_parser2::{Decoder, PixelFormat};
+use std::io::Cursor;
+
+const WIDTH: usize = 13;
+const HEIGHT: usize = 7;
+
+// samples with steps, a ramp and extremes so that every predictor sees differences of both signs
+fn samples(precision: u8) -> Vec<u16> {
+    let top = ((1u32 << precision) - 1) as u16;
+    (0..WIDTH * HEIGHT)
+        .map(|i| {
+            let (x, y) = (i % WIDTH, i / WIDTH);
+            match (x * 5 + y * 3) % 11 {
+                0 => 0,
+                1 => top,
+                n => (n as u16 * 37 + y as u16 * 11) % top,
+            }
+        })
+        .collect()
+}
+
+// a gray SOF3 stream of samples with the predictor and point transform
+fn encode(samples: &[u16], precision: u8, predictor: u8, pt: u8) -> Vec<u8> {
+    let shifted: Vec<i32> = samples.iter().map(|&s| (s >> pt) as i32).collect();
+    let mut writer = BitWriter::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let prediction = predict(&shifted, WIDTH, x, y, predictor, 1 << (precision - pt - 1));
+            // differences are taken modulo 2^16, 32768 is coded as size 16 without further bits
+            match (shifted[y * WIDTH + x] - prediction) as i16 {
+                i16::MIN => writer.symbol(16),
+                diff => writer.value(0, diff as i32),
+            }
+        }
+    }
+    [
+        SOI.to_vec(),
+        sof(0xc3, precision, WIDTH as u16, HEIGHT as u16, &[(1, 1, 0)]),
+        dht(0, 0),
+        sos(&[(1, 0, 0)], predictor, 0, 0, pt),
+        writer.finish(),
+        EOI.to_vec(),
+    ]
+    .concat()
+}
+
+fn decode(data: &[u8]) -> Vec<u16> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    assert!(decoder.is_lossless());
+    let data = decoder.get_image(PixelFormat::Gray16).into_data();
+    data.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect()
+}
+
+#[test]
+fn predictors() {
+    for precision in [8, 12, 16] {
+        let samples = samples(precision);
+        for predictor in 1..=7 {
+            let data = encode(&samples, precision, predictor, 0);
+            assert_eq!(decode(&data), samples, "P={} predictor {}", precision, predictor);
+        }
+    }
+}
+
+#[test]
+fn point_transform() {
+    let samples = samples(12);
+    for pt in [1, 3] {
+        let expected: Vec<u16> = samples.iter().map(|&s| s >> pt << pt).collect();
+        for predictor in [1, 4, 7] {
+            assert_eq!(decode(&encode(&samples, 12, predictor, pt)), expected, "Pt={} predictor {}", pt, predictor);
+        }
+    }
+}
+
+#[test]
+fn gray8_output() {
+    let samples = samples(8);
+    let mut decoder = Decoder::new(Cursor::new(encode(&samples, 8, 6, 0)));
+    decoder.decode().unwrap();
+    let expected: Vec<u8> = samples.iter().map(|&s| s as u8).collect();
+    assert_eq!(decoder.get_image(PixelFormat::Gray8).into_data(), expected);
+}
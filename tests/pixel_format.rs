@@ -0,0 +1,90 @@
+mod common;
+
+use common::{flat, segment, Frame, SOI};
+use jpeg_parser2::{ColorSpace, Decoder, PixelFormat};
+use std::io::Cursor;
+
+// the RGB of four flat blocks side by side, none of their CMYK inks is halfway between two values
+const COLORS: [[i32; 3]; 4] = [[200, 90, 50], [0, 0, 0], [255, 255, 255], [30, 240, 110]];
+
+// a 32x16 RGB frame, marked as such by an Adobe segment with transform 0, of the colors at quantization 8
+fn decoder() -> Decoder<Cursor<Vec<u8>>> {
+    let frame = Frame::new(32, 16, &[(1, 1), (1, 1), (1, 1)], |c, bx, _| flat(COLORS[bx][c] - 128));
+    let adobe = segment(0xee, b"Adobe\x00\x64\x00\x00\x00\x00\x00");
+    let data = frame.baseline(8);
+    let mut decoder = Decoder::new(Cursor::new([SOI.as_slice(), &adobe, &data[2..]].concat()));
+    decoder.decode().unwrap();
+    decoder
+}
+
+fn pixels() -> impl Iterator<Item = [i32; 3]> {
+    (0..32 * 16).map(|i| COLORS[i % 32 / 8])
+}
+
+// the bytes of a pixel in a packed format
+fn packed(pixel_format: PixelFormat, [r, g, b]: [i32; 3]) -> Vec<u8> {
+    let gray = (r * 299 + g * 587 + b * 114 + 500) / 1000;
+    match pixel_format {
+        PixelFormat::Gray8 => vec![gray as u8],
+        PixelFormat::Gray16 => (gray as u16).to_le_bytes().to_vec(),
+        PixelFormat::Rgb8 => vec![r as u8, g as u8, b as u8],
+        PixelFormat::Rgb16 => [r, g, b].iter().flat_map(|&v| (v as u16).to_le_bytes()).collect(),
+        PixelFormat::Rgba8 => vec![r as u8, g as u8, b as u8, 255],
+        PixelFormat::Bgr8 => vec![b as u8, g as u8, r as u8],
+        PixelFormat::Bgra8 => vec![b as u8, g as u8, r as u8, 255],
+        PixelFormat::Rgb565 => (((r >> 3) << 11 | (g >> 2) << 5 | b >> 3) as u16).to_le_bytes().to_vec(),
+        PixelFormat::Cmyk8 => {
+            let k = 255 - r.max(g).max(b);
+            if k == 255 {
+                return vec![0, 0, 0, 255];
+            }
+            let ink = |v: i32| ((255 - k - v) as f64 * 255. / (255 - k) as f64).round() as u8;
+            vec![ink(r), ink(g), ink(b), k as u8]
+        }
+        PixelFormat::YCbCr8Planar => unreachable!(),
+    }
+}
+
+#[test]
+fn packed_formats() {
+    let decoder = decoder();
+    assert_eq!(decoder.get_color_space(), ColorSpace::Rgb);
+    assert_eq!(decoder.get_default_pixel_format(), PixelFormat::Rgb8);
+    for pixel_format in [
+        PixelFormat::Gray8,
+        PixelFormat::Gray16,
+        PixelFormat::Rgb8,
+        PixelFormat::Rgb16,
+        PixelFormat::Rgba8,
+        PixelFormat::Bgr8,
+        PixelFormat::Bgra8,
+        PixelFormat::Rgb565,
+        PixelFormat::Cmyk8,
+    ] {
+        let image = decoder.get_image(pixel_format);
+        assert_eq!((image.get_width(), image.get_height(), image.get_pixel_format()), (32, 16, pixel_format));
+        assert_eq!(image.get_data().len(), 32 * 16 * pixel_format.bytes_per_pixel());
+        let expected: Vec<u8> = pixels().flat_map(|rgb| packed(pixel_format, rgb)).collect();
+        assert_eq!(image.get_data(), expected, "{:?}", pixel_format);
+    }
+}
+
+#[test]
+fn planar_format() {
+    let image = decoder().get_image(PixelFormat::YCbCr8Planar);
+    assert_eq!(image.get_data().len(), 32 * 16 * PixelFormat::YCbCr8Planar.bytes_per_pixel());
+    // RGB frames are converted as in JFIF
+    let ycbcr: Vec<[f64; 3]> = pixels()
+        .map(|[r, g, b]| {
+            let (r, g, b) = (r as f64, g as f64, b as f64);
+            [
+                0.299 * r + 0.587 * g + 0.114 * b,
+                -0.168736 * r - 0.331264 * g + 0.5 * b + 128.,
+                0.5 * r - 0.418688 * g - 0.081312 * b + 128.,
+            ]
+        })
+        .collect();
+    let expected: Vec<u8> =
+        (0..3).flat_map(|c| ycbcr.iter().map(move |v| v[c].round().clamp(0., 255.) as u8)).collect();
+    assert_eq!(image.into_data(), expected);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::{flat, Frame};
+use jpeg_parser2::{Decoder, ErrorKind};
+use std::io::Cursor;
+
+// a 40x24 4:2:0 frame of 3x2 MCUs, with luma blocks and chroma MCUs of their own flat samples
+fn frame() -> Frame {
+    Frame::new(40, 24, &[(2, 2), (1, 1), (1, 1)], |c, bx, by| match c {
+        0 => flat((by * 6 + bx) as i32 * 4 - 48),
+        1 => flat((by * 3 + bx) as i32 * 10),
+        _ => flat(-((by * 3 + bx) as i32) * 10),
+    })
+}
+
+fn decoder(data: &[u8]) -> Decoder<Cursor<&[u8]>> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    decoder
+}
+
+// the samples of the planes at the sampling of their component, from the luma and chroma sample of a pixel
+fn expected(width: usize, height: usize, sample: impl Fn(usize, usize) -> u16) -> Vec<u16> {
+    (0..height).flat_map(|y| (0..width).map(move |x| (x, y))).map(|(x, y)| sample(x, y)).collect()
+}
+
+// the Y, Cb and Cr samples of the frame
+fn samples() -> [Vec<u16>; 3] {
+    [
+        expected(40, 24, |x, y| (128 + (y / 8 * 6 + x / 8) as i32 * 4 - 48) as u16),
+        expected(20, 12, |x, y| 128 + (y / 8 * 3 + x / 8) as u16 * 10),
+        expected(20, 12, |x, y| 128 - (y / 8 * 3 + x / 8) as u16 * 10),
+    ]
+}
+
+#[test]
+fn native_sampling() {
+    let data = frame().baseline(8);
+    let decoder = decoder(&data);
+    let planes = decoder.get_planes();
+    let sizes: Vec<_> = planes.iter().map(|p| (p.width, p.height, p.stride)).collect();
+    // rows are as wide as the MCUs cover, the chroma is half the luma size
+    assert_eq!(sizes, [(40, 24, 48), (20, 12, 24), (20, 12, 24)]);
+    let rows = |c: usize| -> Vec<u16> { (0..planes[c].height).flat_map(|y| planes[c].row(y).to_vec()).collect() };
+    assert_eq!([rows(0), rows(1), rows(2)], samples());
+}
+
+#[test]
+fn y4m() {
+    let data = frame().baseline(8);
+    let decoder = decoder(&data);
+    let mut output = Vec::new();
+    decoder.outputy4m(&mut output).unwrap();
+    let header = b"YUV4MPEG2 W40 H24 F25:1 Ip C420jpeg\nFRAME\n";
+    assert_eq!(&output[..header.len()], header);
+    // the planes one after the other without padding
+    let planes: Vec<u8> = samples().concat().iter().map(|&v| v as u8).collect();
+    assert_eq!(planes.len(), 40 * 24 + 2 * 20 * 12);
+    assert_eq!(&output[header.len()..], planes);
+}
+
+#[test]
+fn y4m_of_12_bits() {
+    let mut frame = Frame::gray(16, 8, |bx, _| flat([1000, -2000][bx]));
+    frame.precision = 12;
+    let data = frame.baseline(8);
+    let mut output = Vec::new();
+    decoder(&data).outputy4m(&mut output).unwrap();
+    // 16-bit little endian samples with the 12 bits at the top
+    let header = b"YUV4MPEG2 W16 H8 F25:1 Ip Cmono16\nFRAME\n";
+    let samples: Vec<u8> = (0..8 * 16).flat_map(|i| ([3048u16, 48][i % 16 / 8] << 4).to_le_bytes()).collect();
+    assert_eq!(output, [header.as_slice(), &samples].concat());
+}
+
+#[test]
+fn y4m_of_unsupported_sampling() {
+    // chroma at a third of the luma rate has no y4m format
+    let frame = Frame::new(24, 8, &[(3, 1), (1, 1), (1, 1)], |_, _, _| flat(0));
+    let data = frame.baseline(8);
+    let err = decoder(&data).outputy4m(&mut Vec::new()).unwrap_err();
+    assert!(matches!(err.kind(), ErrorKind::Io(_)), "{:?}", err.kind());
+}
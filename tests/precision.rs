@@ -0,0 +1,38 @@
+mod common;
+
+use common::{flat, Frame};
+use jpeg_parser2::{Decoder, PixelFormat};
+use std::io::Cursor;
+
+fn decode(data: &[u8], pixel_format: PixelFormat) -> Vec<u8> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    assert_eq!(decoder.get_precision(), 12);
+    assert_eq!(decoder.get_default_pixel_format(), PixelFormat::Gray16);
+    decoder.get_image(pixel_format).into_data()
+}
+
+fn samples(data: &[u8]) -> Vec<u16> {
+    data.chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect()
+}
+
+// a 12-bit gray image of flat blocks at both ends of the range, where a DC coefficient of d at
+// quantization 8 decodes to 2048 + d
+#[test]
+fn extended_sequential() {
+    let mut frame = Frame::gray(24, 8, |bx, _| flat([1000, -2000, 2047][bx]));
+    frame.precision = 12;
+    let data = frame.baseline(8);
+    let expected: Vec<u16> = (0..8).flat_map(|_| (0..24).map(|x| [3048, 48, 4095][x / 8])).collect();
+    assert_eq!(samples(&decode(&data, PixelFormat::Gray16)), expected);
+    let expected: Vec<u8> = expected.iter().map(|&v| (v >> 4) as u8).collect();
+    assert_eq!(decode(&data, PixelFormat::Gray8), expected);
+}
+
+#[test]
+fn quantization_table_of_16_bits() {
+    let mut frame = Frame::gray(8, 8, |_, _| flat(8));
+    frame.precision = 12;
+    let data = frame.baseline(300);
+    assert_eq!(samples(&decode(&data, PixelFormat::Gray16)), vec![2348; 64]);
+}
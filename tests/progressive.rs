@@ -0,0 +1,95 @@
+mod common;
+
+use common::{flat, sos, Frame, EOI};
+use jpeg_parser2::{Decoder, PixelFormat};
+use std::io::Cursor;
+
+fn decode(data: &[u8]) -> Vec<u8> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    decoder.get_image(PixelFormat::Gray8).into_data()
+}
+
+// a gray image of 8 by 2 blocks with a scattering of AC coefficients of both signs and of sizes up to
+// 5 bits, and runs of more than 16 zeros between them
+fn scattered() -> Frame {
+    Frame::gray(64, 16, |bx, by| {
+        let b = by * 8 + bx;
+        let mut block = [0; 64];
+        block[0] = b as i32 * 6 - 40;
+        for (k, coeff) in block.iter_mut().enumerate().skip(1) {
+            if (b * 7 + k * 3) % 11 == 0 || (k == 40 && b % 3 == 0) {
+                *coeff = ((b + k) % 9) as i32 * 3 - 12;
+            }
+        }
+        // a block with a lone coefficient of 1, which only shows in the last refinement
+        if b == 5 {
+            block = flat(block[0]);
+            block[30] = -1;
+        }
+        block
+    })
+}
+
+#[test]
+fn spectral_selection() {
+    let frame = Frame::gray(16, 8, |bx, _| flat(bx as i32 * 8));
+    let data = [
+        frame.headers(0xc2, 8),
+        sos(&[(1, 0, 0)], 0, 0, 0, 0),
+        frame.dc_scan(&[0], 0),
+        sos(&[(1, 0, 0)], 1, 63, 0, 0),
+        frame.ac_scan(0, 1, 63, 0),
+        EOI.to_vec(),
+    ]
+    .concat();
+    let expected: Vec<u8> = (0..8).flat_map(|_| (0..16).map(|x| 128 + (x / 8) * 8)).collect();
+    assert_eq!(decode(&data), expected);
+}
+
+#[test]
+fn successive_approximation() {
+    let frame = scattered();
+    let data = [
+        frame.headers(0xc2, 2),
+        sos(&[(1, 0, 0)], 0, 0, 0, 1),
+        frame.dc_scan(&[0], 1),
+        sos(&[(1, 0, 0)], 1, 5, 0, 2),
+        frame.ac_scan(0, 1, 5, 2),
+        sos(&[(1, 0, 0)], 6, 63, 0, 1),
+        frame.ac_scan(0, 6, 63, 1),
+        sos(&[(1, 0, 0)], 1, 5, 2, 1),
+        frame.ac_refine_scan(0, 1, 5, 1),
+        sos(&[(1, 0, 0)], 0, 0, 1, 0),
+        frame.dc_refine_scan(&[0], 0),
+        sos(&[(1, 0, 0)], 1, 5, 1, 0),
+        frame.ac_refine_scan(0, 1, 5, 0),
+        sos(&[(1, 0, 0)], 6, 63, 1, 0),
+        frame.ac_refine_scan(0, 6, 63, 0),
+        EOI.to_vec(),
+    ]
+    .concat();
+    assert_eq!(decode(&data), decode(&frame.baseline(2)));
+}
+
+#[test]
+fn first_scans_only() {
+    // the image of the coefficients as far as the scans have refined them
+    let frame = scattered();
+    let data = [
+        frame.headers(0xc2, 2),
+        sos(&[(1, 0, 0)], 0, 0, 0, 0),
+        frame.dc_scan(&[0], 0),
+        sos(&[(1, 0, 0)], 1, 63, 0, 1),
+        frame.ac_scan(0, 1, 63, 1),
+        EOI.to_vec(),
+    ]
+    .concat();
+    let mut truncated = scattered();
+    for block in truncated.components[0].blocks.iter_mut() {
+        for coeff in block[1..].iter_mut() {
+            *coeff = coeff.signum() * (coeff.abs() >> 1 << 1);
+        }
+    }
+    assert_eq!(decode(&data), decode(&truncated.baseline(2)));
+}
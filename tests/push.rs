@@ -0,0 +1,79 @@
+mod common;
+
+use common::{dri, sos, Frame, EOI};
+use jpeg_parser2::{Decoder, DecoderBuilder, PixelFormat};
+use std::io::Cursor;
+
+fn block(c: usize, bx: usize, by: usize) -> [i32; 64] {
+    let b = (by * 4 + bx) * 3 + c;
+    let mut block = [0; 64];
+    block[0] = (b % 7) as i32 * 9 - 30;
+    for (k, coeff) in block.iter_mut().enumerate().skip(1) {
+        if (b + k * 5).is_multiple_of(13) {
+            *coeff = ((b + k) % 7) as i32 - 3;
+        }
+    }
+    block
+}
+
+// a color baseline image with restart intervals, a progressive one and an arithmetic coded one
+fn images() -> Vec<Vec<u8>> {
+    let frame = Frame::new(32, 32, &[(2, 2), (1, 1), (1, 1)], block);
+    let ids = [(1, 0, 0), (2, 0, 0), (3, 0, 0)];
+    let restarts = [frame.headers(0xc0, 2), dri(1), sos(&ids, 0, 63, 0, 0), frame.sequential_scan(1), EOI.to_vec()];
+    let mut progressive = vec![frame.headers(0xc2, 2), sos(&ids, 0, 0, 0, 1), frame.dc_scan(&[0, 1, 2], 1)];
+    for (c, &id) in ids.iter().enumerate() {
+        progressive.extend([sos(&[id], 1, 63, 0, 0), frame.ac_scan(c, 1, 63, 0)]);
+    }
+    progressive.extend([sos(&ids, 0, 0, 1, 0), frame.dc_refine_scan(&[0, 1, 2], 0), EOI.to_vec()]);
+    vec![restarts.concat(), progressive.concat(), frame.arithmetic(2)]
+}
+
+fn decode(data: &[u8]) -> Vec<u8> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.decode().unwrap();
+    decoder.get_image(PixelFormat::Rgb8).into_data()
+}
+
+fn decode_pushed<'a>(chunks: impl Iterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut decoder = DecoderBuilder::new().build_push();
+    for chunk in chunks {
+        decoder.feed(chunk).unwrap();
+    }
+    let progress = decoder.finish().unwrap();
+    assert!(progress.headers_ready && progress.done);
+    decoder.get_image(PixelFormat::Rgb8).into_data()
+}
+
+#[test]
+fn split_at_every_byte() {
+    for data in images() {
+        let expected = decode(&data);
+        for split in 0..=data.len() {
+            let (first, second) = data.split_at(split);
+            assert_eq!(decode_pushed([first, second].iter().copied()), expected, "split at {}", split);
+        }
+    }
+}
+
+#[test]
+fn byte_by_byte() {
+    for data in images() {
+        assert_eq!(decode_pushed(data.chunks(1)), decode(&data));
+    }
+}
+
+#[test]
+fn progress() {
+    let data = &images()[0];
+    let mut decoder = DecoderBuilder::new().build_push();
+    // SOI, DQT and the start of the frame header
+    let progress = decoder.feed(&data[..80]).unwrap();
+    assert!(!progress.headers_ready);
+    let progress = decoder.feed(&data[80..data.len() - 2]).unwrap();
+    assert!(progress.headers_ready && !progress.done);
+    assert_eq!(progress.scans_complete, 0);
+    let progress = decoder.feed(&data[data.len() - 2..]).unwrap();
+    assert!(progress.done);
+    assert_eq!(progress.scans_complete, 1);
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use common::{sos, Frame, EOI};
+use jpeg_parser2::{DecoderBuilder, PixelFormat, Scale, Upsampling};
+use std::io::Cursor;
+
+fn block(c: usize, bx: usize, by: usize) -> [i32; 64] {
+    let b = (by * 5 + bx) * 3 + c;
+    let mut block = [0; 64];
+    block[0] = (b % 11) as i32 * 7 - 35;
+    block[1] = (b % 5) as i32 - 2;
+    block[8] = (b % 3) as i32 * 4 - 4;
+    block
+}
+
+fn decode(data: &[u8], builder: &DecoderBuilder) -> Vec<u8> {
+    let mut decoder = builder.build(Cursor::new(data));
+    decoder.decode().unwrap();
+    decoder.get_image(PixelFormat::Rgb8).into_data()
+}
+
+// the bands of next_rows, which follow each other, and their concatenation
+fn decode_rows(data: &[u8], builder: &DecoderBuilder) -> (usize, Vec<u8>) {
+    let mut decoder = builder.build(Cursor::new(data));
+    let mut rows = Vec::new();
+    let mut bands = 0;
+    while let Some((first, band)) = decoder.next_rows(PixelFormat::Rgb8).unwrap() {
+        assert_eq!(first as usize * decoder.get_width() as usize * 3, rows.len());
+        assert_eq!(band.get_width(), decoder.get_width());
+        rows.extend_from_slice(band.get_data());
+        bands += 1;
+    }
+    (bands, rows)
+}
+
+#[test]
+fn bands_of_mcu_rows() {
+    // 3 rows of 16 pixel high MCUs, the last one partial
+    let frame = Frame::new(40, 37, &[(2, 2), (1, 1), (1, 1)], block);
+    let data = frame.baseline(2);
+    for builder in [
+        DecoderBuilder::new(),
+        DecoderBuilder::new().upsampling(Upsampling::Fancy),
+        DecoderBuilder::new().scale(Scale::Half),
+    ] {
+        assert_eq!(decode_rows(&data, &builder), (3, decode(&data, &builder)));
+    }
+}
+
+#[test]
+fn one_band_for_scans_of_single_components() {
+    let frame = Frame::new(40, 37, &[(2, 2), (1, 1), (1, 1)], block);
+    let mut data = frame.headers(0xc0, 2);
+    for c in 0..3 {
+        data.extend(sos(&[(c as u8 + 1, 0, 0)], 0, 63, 0, 0));
+        data.extend(frame.component_scan(c));
+    }
+    data.extend_from_slice(&EOI);
+    let builder = DecoderBuilder::new();
+    assert_eq!(decode_rows(&data, &builder), (1, decode(&frame.baseline(2), &builder)));
+}
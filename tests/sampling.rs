@@ -0,0 +1,78 @@
+mod common;
+
+use common::{flat, Frame};
+use jpeg_parser2::{Decoder, PixelFormat};
+use std::io::Cursor;
+
+// the full size Y, Cb and Cr planes of a frame with quantization 8, where a DC coefficient of d
+// decodes to a flat block of 128 + d
+fn planes(frame: &Frame) -> Vec<u8> {
+    let data = frame.baseline(8);
+    let mut decoder = Decoder::new(Cursor::new(&data));
+    decoder.decode().unwrap();
+    decoder.get_image(PixelFormat::YCbCr8Planar).into_data()
+}
+
+// the planes expected for samples given by component, x and y
+fn expected(width: usize, height: usize, sample: impl Fn(usize, usize, usize) -> i32) -> Vec<u8> {
+    (0..3)
+        .flat_map(|c| (0..width * height).map(move |i| (c, i % width, i / width)))
+        .map(|(c, x, y)| sample(c, x, y) as u8)
+        .collect()
+}
+
+#[test]
+fn luma_three_times_horizontal() {
+    // two MCUs of 24x8, three luma blocks and one of each chroma block
+    let frame = Frame::new(48, 8, &[(3, 1), (1, 1), (1, 1)], |c, bx, _| match c {
+        0 => flat(bx as i32),
+        1 => flat(10 * bx as i32),
+        _ => flat(-10 * bx as i32),
+    });
+    let planes = planes(&frame);
+    assert_eq!(
+        planes,
+        expected(48, 8, |c, x, _| match c {
+            0 => 128 + x as i32 / 8,
+            1 => 128 + 10 * (x as i32 / 24),
+            _ => 128 - 10 * (x as i32 / 24),
+        })
+    );
+}
+
+#[test]
+fn luma_three_times_vertical() {
+    let frame = Frame::new(8, 48, &[(1, 3), (1, 1), (1, 1)], |c, _, by| match c {
+        0 => flat(by as i32),
+        1 => flat(10 * by as i32),
+        _ => flat(-10 * by as i32),
+    });
+    let planes = planes(&frame);
+    assert_eq!(
+        planes,
+        expected(8, 48, |c, _, y| match c {
+            0 => 128 + y as i32 / 8,
+            1 => 128 + 10 * (y as i32 / 24),
+            _ => 128 - 10 * (y as i32 / 24),
+        })
+    );
+}
+
+#[test]
+fn chroma_above_luma() {
+    // one MCU of 16x16 with a single luma block, Cb at twice its rate both ways and Cr vertically
+    let frame = Frame::new(16, 16, &[(1, 1), (2, 2), (1, 2)], |c, bx, by| match c {
+        0 => flat(4),
+        1 => flat(2 * (bx + 2 * by) as i32),
+        _ => flat(-5 * by as i32),
+    });
+    let planes = planes(&frame);
+    assert_eq!(
+        planes,
+        expected(16, 16, |c, x, y| match c {
+            0 => 132,
+            1 => 128 + 2 * (x / 8 + 2 * (y / 8)) as i32,
+            _ => 128 - 5 * (y / 8) as i32,
+        })
+    );
+}
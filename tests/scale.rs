@@ -0,0 +1,67 @@
+mod common;
+
+use common::{flat, Frame};
+use jpeg_parser2::{Decoder, PixelFormat, Scale};
+use std::io::Cursor;
+
+const SCALES: [(Scale, usize); 4] = [(Scale::Full, 1), (Scale::Half, 2), (Scale::Quarter, 4), (Scale::Eighth, 8)];
+
+fn decode(data: &[u8], scale: Scale, pixel_format: PixelFormat) -> (u16, u16, Vec<u8>) {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.set_scale(scale);
+    decoder.decode().unwrap();
+    let image = decoder.get_image(pixel_format);
+    (image.get_width(), image.get_height(), image.into_data())
+}
+
+// the DC coefficient of the block at (bx, by), which decodes to a flat block of 128 + DC at
+// quantization 8
+fn dc(bx: usize, by: usize) -> i32 {
+    (by * 5 + bx) as i32 * 7 - 40
+}
+
+#[test]
+fn flat_blocks() {
+    // a partial block at the right and bottom edges
+    let frame = Frame::gray(36, 20, |bx, by| flat(dc(bx, by)));
+    let data = frame.baseline(8);
+    for (scale, n) in SCALES {
+        let (width, height) = (36usize.div_ceil(n), 20usize.div_ceil(n));
+        let expected: Vec<u8> =
+            (0..width * height).map(|i| (128 + dc(i % width * n / 8, i / width * n / 8)) as u8).collect();
+        assert_eq!(decode(&data, scale, PixelFormat::Gray8), (width as u16, height as u16, expected), "1/{}", n);
+    }
+}
+
+#[test]
+fn eighth_is_the_dc() {
+    // AC coefficients do not change the mean of a block
+    let frame = Frame::gray(32, 16, |bx, by| {
+        let mut block = flat(dc(bx, by));
+        block[1..].iter_mut().enumerate().for_each(|(k, coeff)| *coeff = (k as i32 % 5) - 2);
+        block
+    });
+    let expected: Vec<u8> = (0..8).map(|i| (128 + dc(i % 4, i / 4)) as u8).collect();
+    assert_eq!(decode(&frame.baseline(8), Scale::Eighth, PixelFormat::Gray8), (4, 2, expected));
+}
+
+#[test]
+fn subsampled_chroma() {
+    let frame = Frame::new(32, 32, &[(2, 2), (1, 1), (1, 1)], |c, bx, by| match c {
+        0 => flat(dc(bx, by)),
+        1 => flat(10 * (bx + by) as i32),
+        _ => flat(-5),
+    });
+    let data = frame.baseline(8);
+    for (scale, n) in SCALES {
+        let size = 32 / n;
+        let sample = |c: usize, x: usize, y: usize| match c {
+            0 => 128 + dc(x * n / 8, y * n / 8),
+            1 => 128 + 10 * (x * n / 16 + y * n / 16) as i32,
+            _ => 123,
+        };
+        let expected: Vec<u8> =
+            (0..3 * size * size).map(|i| sample(i / (size * size), i % size, i / size % size) as u8).collect();
+        assert_eq!(decode(&data, scale, PixelFormat::YCbCr8Planar), (size as u16, size as u16, expected), "1/{}", n);
+    }
+}
@@ -0,0 +1,79 @@
+mod common;
+
+use common::Frame;
+use jpeg_parser2::{DecoderBuilder, PixelFormat, Upsampling};
+use std::io::Cursor;
+
+const WIDTH: usize = 45;
+const HEIGHT: usize = 29;
+
+// chroma that varies within blocks, so that every sample differs from its neighbours
+fn block(c: usize, bx: usize, by: usize) -> [i32; 64] {
+    let b = (by * 6 + bx) * 3 + c;
+    let mut block = [0; 64];
+    block[0] = (b % 9) as i32 * 8 - 32;
+    block[1] = (b % 5) as i32 * 6 - 12;
+    block[2] = (b % 7) as i32 * 4 - 12;
+    block[8] = (b % 3) as i32 * 10 - 10;
+    block
+}
+
+// the sample at (x, y) of a plane, with the rows above and below it repeating its edge rows
+fn at(plane: &[Vec<i32>], x: usize, y: isize) -> i32 {
+    plane[y.clamp(0, plane.len() as isize - 1) as usize][x]
+}
+
+// libjpeg's triangle filters of jdsample.c, 3/4 of the nearer and 1/4 of the further sample in
+// each direction that is upsampled, with the edge columns of h2v1 and h2v2 kept as they are
+fn fancy(plane: &[Vec<i32>], h: usize, v: usize) -> Vec<Vec<i32>> {
+    let width = plane[0].len();
+    (0..plane.len() * v)
+        .map(|oy| {
+            let y = (oy / v) as isize;
+            // the column sums of h2v2 and h1v2 weigh the nearer row by 3
+            let (near, bias) = if oy % 2 == 0 { (y - 1, 1) } else { (y + 1, 2) };
+            let column = |x: usize| if v == 2 { 3 * at(plane, x, y) + at(plane, x, near) } else { at(plane, x, y) };
+            (0..width * h)
+                .map(|ox| {
+                    let x = ox / h;
+                    if h == 1 {
+                        return (column(x) + bias) >> 2;
+                    }
+                    let (edge, other, round) = match ox % 2 {
+                        0 => (x == 0, x.max(1) - 1, if v == 2 { 8 } else { 1 }),
+                        _ => (x == width - 1, (x + 1).min(width - 1), if v == 2 { 7 } else { 2 }),
+                    };
+                    match (edge, v) {
+                        (true, 2) => (column(x) * 4 + round) >> 4,
+                        (true, _) => column(x),
+                        (false, 2) => (column(x) * 3 + column(other) + round) >> 4,
+                        (false, _) => (column(x) * 3 + column(other) + round) >> 2,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn triangle_filters() {
+    for (h, v) in [(2, 1), (1, 2), (2, 2)] {
+        let frame = Frame::new(WIDTH as u16, HEIGHT as u16, &[(h as u8, v as u8), (1, 1), (1, 1)], block);
+        let data = frame.baseline(2);
+        let mut decoder = DecoderBuilder::new().upsampling(Upsampling::Fancy).build(Cursor::new(&data));
+        decoder.decode().unwrap();
+        let planes: Vec<Vec<Vec<i32>>> = decoder
+            .get_planes()
+            .iter()
+            .map(|plane| (0..plane.height).map(|y| plane.row(y).iter().map(|&s| s as i32).collect()).collect())
+            .collect();
+        let image = decoder.get_image(PixelFormat::YCbCr8Planar).into_data();
+        for c in 1..3 {
+            let upsampled = fancy(&planes[c], h, v);
+            for (i, &sample) in image[c * WIDTH * HEIGHT..(c + 1) * WIDTH * HEIGHT].iter().enumerate() {
+                let (x, y) = (i % WIDTH, i / WIDTH);
+                assert_eq!(sample as i32, upsampled[y][x], "h{}v{} component {} at ({}, {})", h, v, c, x, y);
+            }
+        }
+    }
+}
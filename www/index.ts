@@ -3,14 +3,18 @@ import { BrowserMessage, WorkerMessage } from './message'
 
 const worker = new Worker()
 
-function readImage(file: File): Promise<Uint8Array> {
-  return new Promise((resolve) => {
-    let reader = new FileReader()
-    reader.addEventListener('load', () => {
-      resolve(new Uint8Array(reader.result as ArrayBuffer))
-    })
-    reader.readAsArrayBuffer(file)
-  })
+// hands the file to the worker chunk by chunk as it is read
+async function sendImage(file: File) {
+  worker.postMessage({ type: 'begin' } as BrowserMessage)
+  const reader = file.stream().getReader()
+  for (;;) {
+    const { done, value } = await reader.read()
+    if (done) {
+      break
+    }
+    worker.postMessage({ type: 'chunk', data: value } as BrowserMessage, [value.buffer])
+  }
+  worker.postMessage({ type: 'end' } as BrowserMessage)
 }
 
 document.body.addEventListener('dragover', (e) => {
@@ -24,7 +28,6 @@ document.body.addEventListener('drop', async (e) => {
   e.preventDefault();
   let files = e.dataTransfer.files;
   let file = files[0];
-  const img = await readImage(file);
   let currentLog = ""
   const appendLog = (s: string) => {
     currentLog += s + "\n"
@@ -34,7 +37,7 @@ document.body.addEventListener('drop', async (e) => {
     const msg = e.data as WorkerMessage
     if (msg.type === 'log') {
       appendLog(msg.message)
-    } else if (msg.type === 'done') {
+    } else {
       const canvas = document.getElementById('canvas') as HTMLCanvasElement
       canvas.width = msg.width
       canvas.height = msg.height
@@ -45,13 +48,11 @@ document.body.addEventListener('drop', async (e) => {
       const idata = new ImageData(msg.width, msg.height)
       idata.data.set(msg.result)
       ctx.putImageData(idata, 0, 0)
-      worker.onmessage = () => {}
+      if (msg.type === 'done') {
+        worker.onmessage = () => {}
+      }
     }
   }
   appendLog('decode start...')
-  console.log(img)
-  worker.postMessage({
-    type: 'start',
-    img,
-  } as BrowserMessage, [img.buffer])
+  await sendImage(file)
 });
\ No newline at end of file
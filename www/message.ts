@@ -2,13 +2,18 @@ export type WorkerMessage = {
   type: 'log',
   message: string,
 } | {
-  type: 'done',
+  // 'frame' is the image decoded so far while the file is still being read
+  type: 'frame' | 'done',
   result: Uint8Array,
   width: number,
   height: number
 }
 
 export type BrowserMessage = {
-  type: 'start',
-  img: Uint8Array,
+  type: 'begin',
+} | {
+  type: 'chunk',
+  data: Uint8Array,
+} | {
+  type: 'end',
 }
@@ -6,6 +6,9 @@ import {BrowserMessage, WorkerMessage} from './message'
 
 import {loadWasm} from './wasm_loader'
 
+// minimum time between two partial frames
+const FRAME_INTERVAL = 100
+
 loadWasm().then(([{Decoder}, {memory}]) => {
   const ctx: Worker = self as any
   function postMessage(message: WorkerMessage, transfer:any = []) {
@@ -15,47 +18,71 @@ loadWasm().then(([{Decoder}, {memory}]) => {
   const decoder = Decoder.new()
   
   console.log('worker start')
+
+  const appendLog = (s:string) => {
+    postMessage({
+      type: 'log',
+      message: s
+    })
+  }
+
+  function postImage(stream: number, type: 'frame' | 'done') {
+    const handle = decoder.get_stream_image(stream)
+    const width = decoder.get_width(handle)
+    const height = decoder.get_height(handle)
+    if (width == 0 || height == 0) {
+      const zero = new Uint8Array(0)
+      postMessage({
+        type,
+        result: zero,
+        width: 0,
+        height: 0,
+      }, [zero.buffer])
+      decoder.free_handle(handle)
+      return
+    }
+    // cf. https://stackoverflow.com/questions/59705741/why-memory-could-not-be-cloned
+    const pix = new Uint8Array(memory.buffer, decoder.get_pix_ptr(handle), width*height*4)
+    const clonedPix = new Uint8Array(width*height*4)
+    clonedPix.set(pix)
+    postMessage({
+      type,
+      result: clonedPix,
+      width,
+      height,
+    }, [clonedPix.buffer])
+    decoder.free_handle(handle)
+  }
+
+  let stream = 0
+  let start = 0
+  let lastFrame = 0
+  let lastRows = -1
   
   ctx.onmessage = (e) => {
     const msg = e.data as BrowserMessage
-    if (msg.type === "start") {
-      const appendLog = (s:string) => {
-        postMessage({
-          type: 'log',
-          message: s
-        })
-      }
+    if (msg.type === 'begin') {
       global['log'] = appendLog
       console.log('decode start at worker')
-      const start = performance.now()
-      const handle = decoder.parse(msg.img);
+      stream = decoder.begin_stream()
+      start = performance.now()
+      lastFrame = start
+      lastRows = -1
+    } else if (msg.type === 'chunk') {
+      const rows = decoder.push_chunk(stream, msg.data)
+      const now = performance.now()
+      if (rows >= 0 && rows != lastRows && now - lastFrame >= FRAME_INTERVAL) {
+        postImage(stream, 'frame')
+        lastFrame = now
+        lastRows = rows
+      }
+    } else if (msg.type === 'end') {
+      decoder.finish_stream(stream)
       const end = performance.now()
       console.log('decode end at worker')
       appendLog("decode end elapsed: " + (end - start) + " sec")
-      const width = decoder.get_width(handle)
-      const height = decoder.get_height(handle)
-      if (width == 0 || height == 0) {
-        const zero = new Uint8Array(0)
-        ctx.postMessage({
-          type: 'done',
-          result: zero,
-          width: 0,
-          height: 0,
-        }, [zero.buffer])
-        return
-      }
-      // cf. https://stackoverflow.com/questions/59705741/why-memory-could-not-be-cloned
-      const pix = new Uint8Array(memory.buffer, decoder.get_pix_ptr(handle), width*height*4)
-      const clonedPix = new Uint8Array(width*height*4)
-      clonedPix.set(pix)
-      ctx.postMessage({
-        type: 'done',
-        result: clonedPix,
-        width,
-        height,
-      }, [clonedPix.buffer])
-      decoder.free_handle(handle);
+      postImage(stream, 'done')
+      decoder.end_stream(stream)
     }
   }
 })
-